@@ -1,22 +1,24 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use linicrypt::linalg::Backend;
 use linicrypt::{AlgebraicRepresentation, CollisionStructure, Direction, Operation};
 
 pub fn criterion_benchmark(c: &mut Criterion) {
-    c.bench_function("single cs check", |b| {
-        use Direction::*;
-        use Operation::*;
-        let p = AlgebraicRepresentation::new(
-            [0, 0, 0, 0, 1],
-            [
-                (E, [1, 0, 1, 0, 0], [1, 0, 0, 0, 0], [0, 0, 0, 1, 0]),
-                (E, [0, 1, 0, 0, 0], [0, 0, 0, 1, 0], [0, 0, 0, 0, 1]),
-            ],
-        );
-        let cs = CollisionStructure {
-            permutation: [1, 0],
-            cs_type: [B, B],
-        };
-        b.iter(|| p.has_cs(black_box(&cs)))
+    use Direction::*;
+    use Operation::*;
+    let p = AlgebraicRepresentation::new(
+        [0, 0, 0, 0, 1],
+        [
+            (E, [1, 0, 1, 0, 0], [1, 0, 0, 0, 0], [0, 0, 0, 1, 0]),
+            (E, [0, 1, 0, 0, 0], [0, 0, 0, 1, 0], [0, 0, 0, 0, 1]),
+        ],
+    );
+    let cs = CollisionStructure {
+        permutation: [1, 0],
+        cs_type: [B, B],
+    };
+    c.bench_function("single cs check", |b| b.iter(|| p.has_cs(black_box(&cs))));
+    c.bench_function("single cs check (real)", |b| {
+        b.iter(|| p.has_cs_with(black_box(&cs), Backend::Real))
     });
 }
 
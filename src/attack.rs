@@ -0,0 +1,350 @@
+//! Turning a collision structure into a concrete collision attack.
+//!
+//! The attack runs the program once on an arbitrary input and then builds a second
+//! execution query by query, in the order of the permutation of the collision
+//! structure. The same queries and the outputs are pinned to their values from the
+//! first execution. Every different query is made to the oracle in its direction,
+//! choosing the rows that are not yet determined freely, and its answer is pinned.
+//! At the end the pinned rows are solved for the base vector of the second execution.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::cipher::SplitMix64;
+use crate::eval::{dot, evaluate, input_coordinates, EvalError, OracleCall};
+use crate::field::Field;
+use crate::linalg::{Backend, LinearSystem};
+use crate::{check_cs, CollisionStructureTrait, Direction, Operation, Oracle, Program};
+
+/// One query of the second execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttackStep {
+    /// The query is the same as in the first execution and is not made again.
+    Reuse { query: usize },
+    /// The query is made to the oracle with `op`, `E` for a forward and `D` for a
+    /// backward query to a cipher, `H` for a random oracle.
+    Query { query: usize, op: Operation },
+}
+
+/// The queries of a collision attack, built from a collision structure of a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttackPlan {
+    pub cs_id: String,
+    pub steps: Vec<AttackStep>,
+}
+
+/// Two different inputs on which a program has the same output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collision<F> {
+    pub inputs: [Vec<F>; 2],
+    pub output: Vec<F>,
+    /// The oracle queries of the second execution, in the order they were made.
+    /// Empty for collisions found by `birthday_attack`.
+    pub queries: Vec<OracleCall<F>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttackError {
+    /// The program can not be run on the input.
+    Eval(EvalError),
+    /// The answer to the query contradicts the rows pinned before it.
+    Inconsistent { query: usize },
+    /// The second execution has the same input or another output than the first.
+    NotACollision,
+}
+
+impl fmt::Display for AttackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttackError::Eval(e) => e.fmt(f),
+            AttackError::Inconsistent { query } => {
+                write!(f, "the answer to query {query} contradicts the pinned rows")
+            }
+            AttackError::NotACollision => write!(f, "the executions do not collide"),
+        }
+    }
+}
+
+impl std::error::Error for AttackError {}
+
+impl From<EvalError> for AttackError {
+    fn from(e: EvalError) -> Self {
+        AttackError::Eval(e)
+    }
+}
+
+impl AttackPlan {
+    /// Returns `None` if `cs` is not a collision structure of `p`. The check is
+    /// done with the exact backend, since the attack computes over the field.
+    pub fn new<F: Field>(p: &impl Program<F>, cs: &impl CollisionStructureTrait) -> Option<Self> {
+        if !check_cs(p, cs, Backend::Exact) {
+            return None;
+        }
+        let reused = cs.same().iter().map(|&query| AttackStep::Reuse { query });
+        let queried = cs.different().map(|(query, dir)| {
+            let op = match (p.query(query).op, dir) {
+                (Operation::H, _) => Operation::H,
+                (_, Direction::F) => Operation::E,
+                (_, Direction::B) => Operation::D,
+            };
+            AttackStep::Query { query, op }
+        });
+        Some(AttackPlan {
+            cs_id: cs.id(),
+            steps: reused.chain(queried).collect(),
+        })
+    }
+
+    /// Runs the attack with `input` as the input of the first execution.
+    pub fn execute<F: Field>(
+        &self,
+        p: &impl Program<F>,
+        oracle: &mut impl Oracle<F>,
+        input: &[F],
+    ) -> Result<Collision<F>, AttackError> {
+        let first = evaluate(p, oracle, input)?.base;
+        let mut system = LinearSystem::new();
+        for j in 0..p.num_outputs() {
+            system.pin(&p.output(j), dot(&p.output(j), &first));
+        }
+        let mut queries = vec![];
+        for step in &self.steps {
+            let (i, op) = match *step {
+                AttackStep::Reuse { query } => {
+                    let c = p.query(query);
+                    for row in [c.k, c.x, c.y] {
+                        if !system.pin(row, dot(row, &first)) {
+                            return Err(AttackError::Inconsistent { query });
+                        }
+                    }
+                    continue;
+                }
+                AttackStep::Query { query, op } => (query, op),
+            };
+            let c = p.query(i);
+            let (k, plain, cipher) = c.cipher_rows();
+            let (input_row, output_row) = match op {
+                Operation::D => (cipher, plain),
+                Operation::E | Operation::H => (plain, cipher),
+            };
+            // The first query made is i^*, which has to differ from the first execution.
+            let mut must_differ = queries.is_empty();
+            let mut values = [F::ZERO; 2];
+            for (value, row) in values.iter_mut().zip([k, input_row]) {
+                *value = match system.value_of(row) {
+                    Some(value) => value,
+                    None if must_differ => {
+                        must_differ = false;
+                        dot(row, &first) + F::ONE
+                    }
+                    None => dot(row, &first),
+                };
+                system.pin(row, *value);
+            }
+            let [k_value, input_value] = values;
+            let output = oracle.query(c.oracle, op, k_value, input_value);
+            if !system.pin(output_row, output) {
+                return Err(AttackError::Inconsistent { query: i });
+            }
+            queries.push(OracleCall {
+                query: i,
+                oracle: c.oracle,
+                op,
+                k: k_value,
+                input: input_value,
+                output,
+            });
+        }
+
+        let second = system.solution(p.base());
+        let coordinates = input_coordinates(p)?;
+        let select = |v: &[F]| -> Vec<F> { coordinates.iter().map(|&j| v[j]).collect() };
+        let inputs = [select(&first), select(&second)];
+        let output: Vec<F> = (0..p.num_outputs())
+            .map(|j| dot(&p.output(j), &first))
+            .collect();
+        // Run the second execution honestly, to make sure the oracle agrees with the
+        // pinned rows.
+        let check = evaluate(p, oracle, &inputs[1])?;
+        if inputs[0] == inputs[1] || check.output != output {
+            return Err(AttackError::NotACollision);
+        }
+        Ok(Collision {
+            inputs,
+            output,
+            queries,
+        })
+    }
+}
+
+impl fmt::Display for AttackPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "attack from collision structure {}", self.cs_id)?;
+        for step in &self.steps {
+            match step {
+                AttackStep::Reuse { query } => writeln!(f, "  reuse query {query}")?,
+                AttackStep::Query { query, op } => writeln!(f, "  query {query} with {op:?}")?,
+            }
+        }
+        write!(f, "  solve for the second input")
+    }
+}
+
+/// The generic attack that works for any program: evaluates `p` on `evaluations`
+/// random inputs and returns the first two that collide, if any.
+///
+/// Programs without a collision structure should only be broken at the rate of
+/// the birthday bound.
+pub fn birthday_attack<F: Field>(
+    p: &impl Program<F>,
+    oracle: &mut impl Oracle<F>,
+    rng: &mut SplitMix64,
+    evaluations: usize,
+) -> Result<Option<Collision<F>>, AttackError> {
+    let num_inputs = input_coordinates(p)?.len();
+    let mut seen: HashMap<Vec<F>, Vec<F>> = HashMap::new();
+    for _ in 0..evaluations {
+        let input: Vec<F> = (0..num_inputs).map(|_| rng.element()).collect();
+        let output = evaluate(p, oracle, &input)?.output;
+        match seen.get(&output) {
+            Some(other) if *other != input => {
+                return Ok(Some(Collision {
+                    inputs: [other.clone(), input],
+                    output,
+                    queries: vec![],
+                }))
+            }
+            _ => {
+                seen.insert(output, input);
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dynamic::all_collision_structures;
+    use crate::field::Fp;
+    use crate::AlgebraicRepresentation;
+    use Operation::*;
+
+    type Gf251 = Fp<251>;
+
+    // E(k, x) = (x + k)^3 + k is a permutation in x for every key, since 3 is
+    // coprime to 250. Its inverse takes the cube root with the exponent 167.
+    struct CubeCipher;
+
+    fn pow(a: Gf251, exp: u32) -> Gf251 {
+        (0..exp).fold(Gf251::ONE, |acc, _| acc * a)
+    }
+
+    impl Oracle<Gf251> for CubeCipher {
+        fn query(&mut self, oracle: usize, op: Operation, k: Gf251, input: Gf251) -> Gf251 {
+            let k = k + Gf251::from_u64(oracle as u64);
+            match op {
+                E => pow(input + k, 3) + k,
+                D => pow(input - k, 167) - k,
+                H => pow(input + k + k, 2) + k,
+            }
+        }
+    }
+
+    #[test]
+    fn check_cube_cipher() {
+        for x in Gf251::elements().step_by(7) {
+            let k = Gf251::from_u64(3);
+            let y = CubeCipher.query(1, E, k, x);
+            assert_eq!(CubeCipher.query(1, D, k, y), x);
+        }
+    }
+
+    #[test]
+    fn check_attack_without_feed_forward() {
+        // y = E(a, b), output y
+        let p = AlgebraicRepresentation::<3, 1, 1, Gf251>::from_coefficients(
+            [0, 0, 1],
+            [(E, [1, 0, 0], [0, 1, 0], [0, 0, 1])],
+        );
+        let mut found = 0;
+        for cs in all_collision_structures(1) {
+            let Some(plan) = AttackPlan::new(&p, &cs) else {
+                continue;
+            };
+            let input = [Gf251::from_u64(5), Gf251::from_u64(8)];
+            let collision = plan.execute(&p, &mut CubeCipher, &input).unwrap();
+            assert_eq!(collision.inputs[0], input);
+            assert_ne!(collision.inputs[0], collision.inputs[1]);
+            assert_eq!(collision.queries.len(), 1);
+            assert_eq!(collision.queries[0].op, D);
+            found += 1;
+        }
+        assert_eq!(found, 1);
+    }
+
+    #[test]
+    fn check_attacks_on_two_query_program() {
+        // y0 = E(a, b), y1 = E(c, y0), output y1
+        let p = AlgebraicRepresentation::<5, 2, 1, Gf251>::from_coefficients(
+            [0, 0, 0, 0, 1],
+            [
+                (E, [1, 0, 0, 0, 0], [0, 1, 0, 0, 0], [0, 0, 0, 1, 0]),
+                (E, [0, 0, 1, 0, 0], [0, 0, 0, 1, 0], [0, 0, 0, 0, 1]),
+            ],
+        );
+        let input = [1, 2, 3].map(Gf251::from_u64);
+        let mut found = 0;
+        for cs in all_collision_structures(2) {
+            match AttackPlan::new(&p, &cs) {
+                Some(plan) => {
+                    let collision = plan.execute(&p, &mut CubeCipher, &input).unwrap();
+                    assert_ne!(collision.inputs[0], collision.inputs[1]);
+                    found += 1;
+                }
+                None => assert!(!p.has_cs(&cs)),
+            }
+        }
+        assert!(found > 0);
+
+        let bb = all_collision_structures(2)
+            .find(|cs| cs.id() == "10,0,BB")
+            .unwrap();
+        let plan = AttackPlan::new(&p, &bb).unwrap();
+        assert_eq!(
+            plan.to_string(),
+            "attack from collision structure 10,0,BB\n  query 1 with D\n  query 0 with D\n  solve for the second input"
+        );
+    }
+
+    #[test]
+    fn check_no_plan_for_davies_meyer() {
+        // y = E(a, b), output y + b
+        let p = AlgebraicRepresentation::<3, 1, 1, Gf251>::from_coefficients(
+            [0, 1, 1],
+            [(E, [1, 0, 0], [0, 1, 0], [0, 0, 1])],
+        );
+        assert!(all_collision_structures(1).all(|cs| AttackPlan::new(&p, &cs).is_none()));
+    }
+
+    #[test]
+    fn check_birthday_attack() {
+        use crate::cipher::LazyCipher;
+        use crate::field::Gf256;
+        // y = E(a, b), output a, collides as soon as a repeats.
+        let p = AlgebraicRepresentation::<3, 1, 1, Gf256>::from_coefficients(
+            [1, 0, 0],
+            [(E, [1, 0, 0], [0, 1, 0], [0, 0, 1])],
+        );
+        let mut cipher = LazyCipher::new(0);
+        let mut rng = SplitMix64::new(0);
+        let collision = birthday_attack(&p, &mut cipher, &mut rng, 256)
+            .unwrap()
+            .unwrap();
+        assert_eq!(collision.inputs[0][0], collision.inputs[1][0]);
+        assert_ne!(collision.inputs[0], collision.inputs[1]);
+        assert!(birthday_attack(&p, &mut cipher, &mut rng, 1)
+            .unwrap()
+            .is_none());
+    }
+}
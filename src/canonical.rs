@@ -0,0 +1,284 @@
+//! Canonical forms of programs up to a change of basis of the inputs.
+//!
+//! Two programs are equivalent if one is obtained from the other by an invertible
+//! linear change of the input variables, and by writing the query answers to other
+//! coordinates of the base vector. Equivalent programs are degenerate together and
+//! have the same collision structures, so it is enough to analyze one of each class.
+//!
+//! A change of basis multiplies the input part of every row from the right with an
+//! invertible matrix, that is it applies column operations to the input columns. The
+//! canonical form brings these columns into reduced column echelon form, which is
+//! unique for all programs of a class.
+//!
+//! Queries that do not use each other's answers can also be made in the other order.
+//! Programs that only differ in the order of such queries have the same collision
+//! structures up to renaming the queries, see `DynCollisionStructure::reorder_queries`.
+//! `symmetry_classes` collapses them as well.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use itertools::Itertools;
+use na::*;
+use nalgebra as na;
+
+use crate::eval::input_coordinates;
+use crate::field::Field;
+use crate::validate::answer_coordinate;
+use crate::{AlgebraicRepresentation, Constraint, Operation, Program};
+
+impl<const BASE: usize, const N: usize, const OUT: usize, F: Field>
+    AlgebraicRepresentation<BASE, N, OUT, F>
+{
+    /// The representative of the class of this program. The inputs come first in the
+    /// base vector, followed by the answers of the queries in order, and the input
+    /// columns of the rows `m, k0, x0, k1, x1, ...` are in reduced column echelon form.
+    ///
+    /// # Panics
+    /// If the program is not valid.
+    pub fn canonical_form(&self) -> Self {
+        let inputs = input_coordinates(self).unwrap_or_else(|e| panic!("{e}"));
+        let columns: Vec<usize> = inputs
+            .iter()
+            .copied()
+            .chain((0..N).map(|i| answer_coordinate(self.query(i).y).unwrap()))
+            .collect();
+        let permute = |row: &[F]| -> Vec<F> { columns.iter().map(|&j| row[j]).collect() };
+
+        // The rows in the order the echelon form is computed in.
+        let mut rows: Vec<Vec<F>> = (0..OUT).map(|j| permute(&self.output(j))).collect();
+        for c in &self.constraints {
+            rows.push(permute(c.k.as_slice()));
+            rows.push(permute(c.x.as_slice()));
+        }
+        column_echelon_form(&mut rows, inputs.len());
+
+        let row = |r: &[F]| RowSVector::<F, BASE>::from_row_slice(r);
+        let mut y = RowSVector::<F, BASE>::from_element(F::ZERO);
+        AlgebraicRepresentation {
+            m: SMatrix::from_fn(|j, col| rows[j][col]),
+            constraints: std::array::from_fn(|i| {
+                let c = &self.constraints[i];
+                y.fill(F::ZERO);
+                y[inputs.len() + i] = F::ONE;
+                Constraint {
+                    op: c.op,
+                    oracle: c.oracle,
+                    k: row(&rows[OUT + 2 * i]),
+                    x: row(&rows[OUT + 2 * i + 1]),
+                    y,
+                }
+            }),
+        }
+    }
+
+    /// The program that makes the query `order[j]` of this program as its query `j`.
+    /// The answers stay at their coordinates of the base vector. Returns `None` if a
+    /// query would use the answer of a later one.
+    pub fn reorder_queries(&self, order: [usize; N]) -> Option<Self> {
+        let p = AlgebraicRepresentation {
+            m: self.m,
+            constraints: order.map(|i| self.constraints[i].clone()),
+        };
+        p.validate().ok()?;
+        Some(p)
+    }
+
+    /// The representative of the class of this program up to a change of basis and
+    /// the order of independent queries, that is the smallest canonical form of all
+    /// valid reorderings of the queries. Also returns the order of the queries of this
+    /// program that leads to it, in the form `reorder_queries` takes it.
+    ///
+    /// # Panics
+    /// If the program is not valid.
+    pub fn symmetric_form(&self) -> (Self, [usize; N]) {
+        (0..N)
+            .permutations(N)
+            .filter_map(|order| {
+                let order: [usize; N] = order.try_into().unwrap();
+                Some((self.reorder_queries(order)?.canonical_form(), order))
+            })
+            .min_by_key(|(p, _)| sort_key(p))
+            .unwrap_or_else(|| panic!("{}", self.validate().unwrap_err()))
+    }
+}
+
+// Orders programs by their queries first and then by their coefficients.
+#[allow(clippy::type_complexity)]
+fn sort_key<const BASE: usize, const N: usize, const OUT: usize, F: Field>(
+    p: &AlgebraicRepresentation<BASE, N, OUT, F>,
+) -> (Vec<(Operation, usize)>, Vec<F>) {
+    let queries = p.constraints.iter().map(|c| (c.op, c.oracle)).collect();
+    let rows = p.m.transpose();
+    let coefficients = rows.iter().chain(
+        p.constraints
+            .iter()
+            .flat_map(|c| c.k.iter().chain(c.x.iter()).chain(c.y.iter())),
+    );
+    (queries, coefficients.copied().collect())
+}
+
+// Brings the first `inputs` columns of `rows` into reduced column echelon form: every
+// row either has a leading 1 in a column of its own, with zeros in all other input
+// columns, or only uses the columns of the leading ones of the rows above it.
+fn column_echelon_form<F: Field>(rows: &mut [Vec<F>], inputs: usize) {
+    let mut pivot = 0;
+    for r in 0..rows.len() {
+        let Some(j) = (pivot..inputs).find(|&j| !rows[r][j].is_zero()) else {
+            continue;
+        };
+        let scale = rows[r][j].inverse().unwrap();
+        for row in rows.iter_mut() {
+            row.swap(pivot, j);
+            row[pivot] = row[pivot] * scale;
+        }
+        for j in (0..inputs).filter(|&j| j != pivot) {
+            let factor = rows[r][j];
+            if factor.is_zero() {
+                continue;
+            }
+            for row in rows.iter_mut() {
+                row[j] = row[j] - factor * row[pivot];
+            }
+        }
+        pivot += 1;
+        if pivot == inputs {
+            break;
+        }
+    }
+}
+
+/// Groups `programs` into their classes. Returns the canonical form of every class
+/// together with the number of programs in it, in the order the classes first appear.
+///
+/// If the programs are closed under changes of basis of the inputs, as all the
+/// programs of a given shape are, the canonical forms are among them and the class
+/// sizes add up to their number.
+pub fn equivalence_classes<const BASE: usize, const N: usize, const OUT: usize, F: Field>(
+    programs: impl IntoIterator<Item = AlgebraicRepresentation<BASE, N, OUT, F>>,
+) -> Vec<(AlgebraicRepresentation<BASE, N, OUT, F>, usize)> {
+    classes_by(programs, |p| p.canonical_form())
+}
+
+/// Like `equivalence_classes`, but also collapses programs that only differ in the
+/// order of independent queries. Returns the `symmetric_form` of every class.
+pub fn symmetry_classes<const BASE: usize, const N: usize, const OUT: usize, F: Field>(
+    programs: impl IntoIterator<Item = AlgebraicRepresentation<BASE, N, OUT, F>>,
+) -> Vec<(AlgebraicRepresentation<BASE, N, OUT, F>, usize)> {
+    classes_by(programs, |p| p.symmetric_form().0)
+}
+
+fn classes_by<P, K: Clone + Eq + Hash>(
+    programs: impl IntoIterator<Item = P>,
+    representative: impl Fn(&P) -> K,
+) -> Vec<(K, usize)> {
+    let mut index: HashMap<_, usize> = HashMap::new();
+    let mut classes: Vec<(_, usize)> = vec![];
+    for p in programs {
+        let canonical = representative(&p);
+        match index.get(&canonical) {
+            Some(&i) => classes[i].1 += 1,
+            None => {
+                index.insert(canonical.clone(), classes.len());
+                classes.push((canonical, 1));
+            }
+        }
+    }
+    classes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::Fp;
+    use crate::Operation::*;
+
+    #[test]
+    fn check_change_of_basis() {
+        // y0 = E(a, b), y1 = E(c, y0), output y1 + a
+        let p = AlgebraicRepresentation::<5, 2, 1, Fp<3>>::from_coefficients(
+            [1, 0, 0, 0, 1],
+            [
+                (E, [1, 0, 0, 0, 0], [0, 1, 0, 0, 0], [0, 0, 0, 1, 0]),
+                (E, [0, 0, 1, 0, 0], [0, 0, 0, 1, 0], [0, 0, 0, 0, 1]),
+            ],
+        );
+        // The same program after substituting a = a + 2b + 2c, b = 2a + b and c = b.
+        let q = AlgebraicRepresentation::<5, 2, 1, Fp<3>>::from_coefficients(
+            [1, 2, 2, 0, 1],
+            [
+                (E, [1, 2, 2, 0, 0], [2, 1, 0, 0, 0], [0, 0, 0, 1, 0]),
+                (E, [0, 1, 0, 0, 0], [0, 0, 0, 1, 0], [0, 0, 0, 0, 1]),
+            ],
+        );
+        assert_eq!(p.canonical_form(), p);
+        assert_eq!(q.canonical_form(), p);
+    }
+
+    #[test]
+    fn check_answers_are_moved_back() {
+        // y0 = D(a, b), y1 = E(y0, c), output y1, with c after y0 in the base vector.
+        let p = AlgebraicRepresentation::<5, 2, 1>::new(
+            [0, 0, 0, 0, 1],
+            [
+                (D, [1, 0, 0, 0, 0], [0, 1, 0, 0, 0], [0, 0, 1, 0, 0]),
+                (E, [0, 0, 1, 0, 0], [0, 0, 0, 1, 0], [0, 0, 0, 0, 1]),
+            ],
+        );
+        let q = AlgebraicRepresentation::<5, 2, 1>::new(
+            [0, 0, 0, 0, 1],
+            [
+                (D, [1, 0, 0, 0, 0], [0, 1, 0, 0, 0], [0, 0, 0, 1, 0]),
+                (E, [0, 0, 0, 1, 0], [0, 0, 1, 0, 0], [0, 0, 0, 0, 1]),
+            ],
+        );
+        assert_eq!(p.canonical_form(), q);
+        assert_ne!(p, q);
+    }
+
+    #[test]
+    fn check_equivalence_classes() {
+        // y = E(a, b) and y = E(b, a) with the outputs y and y + a.
+        let programs = [[0, 0, 1], [1, 0, 1]].into_iter().flat_map(|m| {
+            [([1, 0, 0], [0, 1, 0]), ([0, 1, 0], [1, 0, 0])]
+                .map(|(k, x)| AlgebraicRepresentation::<3, 1, 1>::new(m, [(E, k, x, [0, 0, 1])]))
+        });
+        let classes = equivalence_classes(programs);
+        let sizes: Vec<_> = classes.iter().map(|(_, size)| *size).collect();
+        // Swapping a and b maps E(b, a) to E(a, b), but the output y + a to y + b.
+        assert_eq!(sizes, [2, 1, 1]);
+    }
+
+    #[test]
+    fn check_reordered_queries() {
+        use crate::dynamic::{all_collision_structures, DynCollisionStructure};
+        use crate::CollisionStructureTrait;
+        // y0 = E(a, b), y1 = D(c, a + b), output y0 + y1
+        let p = AlgebraicRepresentation::<5, 2, 1>::new(
+            [0, 0, 0, 1, 1],
+            [
+                (E, [1, 0, 0, 0, 0], [0, 1, 0, 0, 0], [0, 0, 0, 1, 0]),
+                (D, [0, 0, 1, 0, 0], [1, 1, 0, 0, 0], [0, 0, 0, 0, 1]),
+            ],
+        );
+        let q = p.reorder_queries([1, 0]).unwrap();
+        assert_eq!(p.symmetric_form().0, q.symmetric_form().0);
+        for cs in all_collision_structures(2) {
+            let reordered = cs.reorder_queries(&[1, 0]);
+            assert_eq!(p.has_cs(&cs), q.has_cs(&reordered), "{}", cs.id());
+        }
+        let cs: DynCollisionStructure = "01,0,FB".parse().unwrap();
+        assert_eq!(cs.reorder_queries(&[1, 0]).id(), "10,0,FB");
+
+        // The second query of the cascade uses the answer of the first.
+        let cascade = AlgebraicRepresentation::<5, 2, 1>::new(
+            [0, 0, 0, 0, 1],
+            [
+                (E, [1, 0, 0, 0, 0], [0, 1, 0, 0, 0], [0, 0, 0, 1, 0]),
+                (E, [0, 0, 1, 0, 0], [0, 0, 0, 1, 0], [0, 0, 0, 0, 1]),
+            ],
+        );
+        assert_eq!(cascade.reorder_queries([1, 0]), None);
+        assert_eq!(cascade.symmetric_form(), (cascade.canonical_form(), [0, 1]));
+    }
+}
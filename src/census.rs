@@ -0,0 +1,234 @@
+//! Counting which collision structures apply to many programs, on all cores.
+//!
+//! Which programs count as degenerate is up to the caller, usually the rank deficient
+//! ones, see `degeneracy`.
+//!
+//! A `Census` only holds counts, so the censuses of disjoint sets of programs can be
+//! merged in any order and give the same result. `census` uses this to check the
+//! programs in parallel with `rayon`.
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use rayon::prelude::*;
+
+use crate::degeneracy::{has_degeneracy, DegeneracyKind};
+use crate::dynamic::DynCollisionStructure;
+use crate::field::Field;
+use crate::linalg::Backend;
+use crate::{CollisionStructureTrait, Program};
+
+/// How many of a set of programs are degenerate, and how many of the others have
+/// each collision structure and each combination of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Census {
+    /// The ids of the collision structures, in the order of `per_cs` and of the
+    /// entries of the combinations.
+    pub ids: Vec<String>,
+    /// The number of programs, including the degenerate ones.
+    pub programs: usize,
+    pub degenerate: usize,
+    pub per_cs: Vec<usize>,
+    /// A combination has one entry per collision structure, `1` if it applies and
+    /// `0` otherwise. Only combinations that occurred are stored.
+    pub combinations: BTreeMap<Vec<usize>, usize>,
+}
+
+impl Census {
+    /// An empty census for the collision structures `css`.
+    pub fn new(css: &[DynCollisionStructure]) -> Self {
+        Census {
+            ids: css.iter().map(|cs| cs.id()).collect(),
+            programs: 0,
+            degenerate: 0,
+            per_cs: vec![0; css.len()],
+            combinations: BTreeMap::new(),
+        }
+    }
+
+    /// Counts `count` non-degenerate programs with the collision structures in
+    /// `combination`.
+    pub fn record(&mut self, combination: &[usize], count: usize) {
+        assert_eq!(combination.len(), self.ids.len());
+        self.programs += count;
+        for (n, &applies) in self.per_cs.iter_mut().zip(combination) {
+            *n += applies * count;
+        }
+        *self.combinations.entry(combination.to_vec()).or_insert(0) += count;
+    }
+
+    pub fn record_degenerate(&mut self, count: usize) {
+        self.programs += count;
+        self.degenerate += count;
+    }
+
+    /// Adds the counts of `other`, which has to be a census for the same structures.
+    pub fn merge(mut self, other: Census) -> Census {
+        assert_eq!(
+            self.ids, other.ids,
+            "censuses of different collision structures"
+        );
+        self.programs += other.programs;
+        self.degenerate += other.degenerate;
+        for (n, m) in self.per_cs.iter_mut().zip(other.per_cs) {
+            *n += m;
+        }
+        for (combination, count) in other.combinations {
+            *self.combinations.entry(combination).or_insert(0) += count;
+        }
+        self
+    }
+
+    /// The number of non-degenerate programs without any of the collision structures.
+    pub fn without_cs(&self) -> usize {
+        let none = vec![0; self.ids.len()];
+        self.combinations.get(&none).copied().unwrap_or(0)
+    }
+}
+
+/// Which of the structures in `css` apply to `p`, in the form `Census::record` takes.
+pub fn combination<F: Field>(
+    p: &impl Program<F>,
+    css: &[DynCollisionStructure],
+    backend: Backend,
+) -> Vec<usize> {
+    css.iter()
+        .map(|cs| {
+            assert_eq!(cs.num_queries(), p.num_queries());
+            crate::check_cs(p, cs, backend) as usize
+        })
+        .collect()
+}
+
+// The number of programs taken from the iterator at a time and checked in parallel.
+pub(crate) const BATCH: usize = 1 << 14;
+
+/// Checks all `programs` against `css` on all cores, those with a degeneracy of one of
+/// the kinds in `degenerate` are only counted as degenerate. The programs are taken
+/// from the iterator in batches, so it can enumerate more programs than fit into memory.
+pub fn census<F: Field, P: Program<F> + Send>(
+    programs: impl IntoIterator<Item = P>,
+    css: &[DynCollisionStructure],
+    backend: Backend,
+    degenerate: &[DegeneracyKind],
+) -> Census {
+    let mut total = Census::new(css);
+    for batch in &programs.into_iter().chunks(BATCH) {
+        let batch = batch.collect();
+        let (census, _) = census_of_batch(batch, css, backend, degenerate, |_| false);
+        total = total.merge(census);
+    }
+    total
+}
+
+// The census of `batch`, and the positions in the batch of the non-degenerate programs
+// whose combination is selected by `select`, with their combinations in order.
+pub(crate) fn census_of_batch<F: Field, P: Program<F> + Send>(
+    batch: Vec<P>,
+    css: &[DynCollisionStructure],
+    backend: Backend,
+    degenerate: &[DegeneracyKind],
+    select: impl Fn(&[usize]) -> bool + Sync,
+) -> (Census, Vec<(usize, Vec<usize>)>) {
+    let (census, mut selected) = batch
+        .into_par_iter()
+        .enumerate()
+        .fold(
+            || (Census::new(css), vec![]),
+            |(mut census, mut selected), (position, p)| {
+                if has_degeneracy(&p, degenerate, backend) {
+                    census.record_degenerate(1);
+                } else {
+                    let combination = combination(&p, css, backend);
+                    census.record(&combination, 1);
+                    if select(&combination) {
+                        selected.push((position, combination));
+                    }
+                }
+                (census, selected)
+            },
+        )
+        .reduce(
+            || (Census::new(css), vec![]),
+            |(a, mut selected), (b, other)| {
+                selected.extend(other);
+                (a.merge(b), selected)
+            },
+        );
+    selected.sort();
+    (census, selected)
+}
+
+/// Applies `check` to all `programs` on all cores, and hands every program with its
+/// result to `consume`, in the order of the iterator. Like `census`, this only keeps a
+/// batch of programs in memory at a time.
+pub fn check_in_order<P: Send + Sync, R: Send>(
+    programs: impl IntoIterator<Item = P>,
+    check: impl Fn(&P) -> R + Sync,
+    mut consume: impl FnMut(P, R),
+) {
+    for batch in &programs.into_iter().chunks(BATCH) {
+        let batch: Vec<P> = batch.collect();
+        let results: Vec<R> = batch.par_iter().map(&check).collect();
+        for (p, result) in batch.into_iter().zip(results) {
+            consume(p, result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dynamic::collision_structures;
+    use crate::field::Gf2;
+    use crate::space::ProgramSpace;
+
+    #[test]
+    fn check_census_matches_sequential_count() {
+        let space = ProgramSpace::<Gf2>::new(2, 1, 1).with_last_answer_in_output();
+        let css: Vec<_> = collision_structures(1, 1).collect();
+        let rank_deficient = [DegeneracyKind::RankDeficient];
+        let parallel = census(&space, &css, Backend::Exact, &rank_deficient);
+
+        let mut sequential = Census::new(&css);
+        for p in &space {
+            if p.is_degenerate() {
+                sequential.record_degenerate(1);
+            } else {
+                sequential.record(&combination(&p, &css, Backend::Exact), 1);
+            }
+        }
+        assert_eq!(parallel, sequential);
+        assert_eq!(parallel.programs, 64);
+    }
+
+    #[test]
+    fn check_in_order_keeps_the_order() {
+        let mut seen = vec![];
+        check_in_order(
+            0..3 * BATCH + 5,
+            |&i| i * i,
+            |i, square| seen.push((i, square)),
+        );
+        assert!(seen
+            .iter()
+            .copied()
+            .eq((0..3 * BATCH + 5).map(|i| (i, i * i))));
+    }
+
+    #[test]
+    fn check_merge() {
+        let css: Vec<_> = collision_structures(2, 1).collect();
+        let mut a = Census::new(&css);
+        a.record(&[1, 0, 0, 1], 2);
+        let mut b = Census::new(&css);
+        b.record(&[1, 0, 0, 1], 1);
+        b.record(&[0, 0, 0, 0], 5);
+        b.record_degenerate(3);
+        let merged = a.clone().merge(b.clone());
+        assert_eq!(merged, b.merge(a));
+        assert_eq!(merged.programs, 11);
+        assert_eq!(merged.per_cs, [3, 0, 0, 3]);
+        assert_eq!(merged.without_cs(), 5);
+    }
+}
@@ -0,0 +1,605 @@
+//! Saving the state of a long census to disk, to resume it after an interruption or to
+//! merge it with the censuses of other parts of the program space.
+//!
+//! A checkpoint is a small text file with the range of indices of the programs to
+//! count, the position up to which they are counted, the census of these programs, and
+//! the indices of the programs that were selected among them:
+//!
+//! ```text
+//! linicrypt checkpoint
+//! description 4 inputs, 2 queries, 1 outputs over a field of order 2 with E0, using the last answer, Exact ranks, degenerate if rank-deficient
+//! range 0..8388608
+//! position 16384
+//! programs 16384
+//! degenerate 9216
+//! cs 01,0,FF 2048
+//! ...
+//! combination 000000000000 1024
+//! ...
+//! selected 22 000000000100
+//! ...
+//! ```
+//!
+//! The description identifies the run, a checkpoint is only resumed or merged with
+//! checkpoints of the same run. A checkpoint whose position is the end of its range is
+//! the result of a shard, the part of a run that is done by one process.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::census::{census_of_batch, Census, BATCH};
+use crate::degeneracy::DegeneracyKind;
+use crate::dynamic::DynCollisionStructure;
+use crate::field::Field;
+use crate::linalg::Backend;
+use crate::space::ProgramSpace;
+
+const HEADER: &str = "linicrypt checkpoint";
+
+/// The programs with indices in `range` before `position` are counted in `census`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub description: String,
+    pub range: Range<u128>,
+    pub position: u128,
+    pub census: Census,
+    /// The indices of the selected programs among the counted ones, with their
+    /// combinations of collision structures.
+    pub selected: BTreeMap<u128, Vec<usize>>,
+}
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    /// The line with the (1-based) number `line` is not what was expected.
+    Format {
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::Io(e) => e.fmt(f),
+            CheckpointError::Format { line, message } => {
+                write!(f, "line {line} of the checkpoint: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+/// Why the results of shards cannot be merged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeError {
+    NoShards,
+    /// The shards are parts of the runs with these descriptions.
+    DifferentRuns(String, String),
+    /// The shard of this range has not counted the programs from this position on.
+    Incomplete(Range<u128>, u128),
+    /// One shard ends at the first index, but the next one starts at the second.
+    NotAdjacent(u128, u128),
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeError::NoShards => write!(f, "there are no shards to merge"),
+            MergeError::DifferentRuns(a, b) => {
+                write!(f, "the shards are parts of different runs: {a:?} and {b:?}")
+            }
+            MergeError::Incomplete(range, position) => write!(
+                f,
+                "the shard of {}..{} stopped at {position}",
+                range.start, range.end
+            ),
+            MergeError::NotAdjacent(end, start) => {
+                write!(
+                    f,
+                    "a shard ends at {end}, but the next one starts at {start}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+impl From<io::Error> for CheckpointError {
+    fn from(e: io::Error) -> Self {
+        CheckpointError::Io(e)
+    }
+}
+
+impl Checkpoint {
+    /// The state before counting the programs in `range` for the collision structures
+    /// `css`.
+    pub fn new(description: String, range: Range<u128>, css: &[DynCollisionStructure]) -> Self {
+        Checkpoint {
+            description,
+            position: range.start,
+            range,
+            census: Census::new(css),
+            selected: BTreeMap::new(),
+        }
+    }
+
+    /// Whether all programs of the range are counted.
+    pub fn is_complete(&self) -> bool {
+        self.position == self.range.end
+    }
+
+    /// Writes the checkpoint to `path`. The file is replaced at once, so an
+    /// interruption while writing leaves the previous checkpoint intact.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut file = io::BufWriter::new(fs::File::create(&tmp)?);
+        self.write(&mut file)?;
+        file.into_inner()?.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, CheckpointError> {
+        Self::read(BufReader::new(fs::File::open(path)?))
+    }
+
+    pub fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        let census = &self.census;
+        writeln!(w, "{HEADER}")?;
+        writeln!(w, "description {}", self.description)?;
+        writeln!(w, "range {}..{}", self.range.start, self.range.end)?;
+        writeln!(w, "position {}", self.position)?;
+        writeln!(w, "programs {}", census.programs)?;
+        writeln!(w, "degenerate {}", census.degenerate)?;
+        for (id, count) in census.ids.iter().zip(&census.per_cs) {
+            writeln!(w, "cs {id} {count}")?;
+        }
+        for (combination, count) in &census.combinations {
+            writeln!(w, "combination {} {count}", bits(combination))?;
+        }
+        for (index, combination) in &self.selected {
+            writeln!(w, "selected {index} {}", bits(combination))?;
+        }
+        Ok(())
+    }
+
+    pub fn read(r: impl BufRead) -> Result<Self, CheckpointError> {
+        let mut lines = Lines {
+            lines: r.lines(),
+            number: 0,
+            peeked: None,
+        };
+        let header = lines.next()?.unwrap_or_default();
+        if header != HEADER {
+            return Err(lines.error(format!("expected {HEADER:?}")));
+        }
+        let description = lines.field("description")?;
+        let range = lines.field("range")?;
+        let range = range
+            .split_once("..")
+            .and_then(|(start, end)| Some(start.parse().ok()?..end.parse().ok()?))
+            .ok_or_else(|| lines.error(format!("{range:?} is not a range")))?;
+        let position = lines.number("position")?;
+        if !(range.start..=range.end).contains(&position) {
+            return Err(lines.error(format!(
+                "position {position} is outside of the range {}..{}",
+                range.start, range.end
+            )));
+        }
+        let programs: usize = lines.number("programs")?;
+        if programs as u128 != position - range.start {
+            return Err(lines.error(format!(
+                "{programs} programs were counted, but {} were checked",
+                position - range.start
+            )));
+        }
+        let degenerate = lines.number("degenerate")?;
+
+        let (mut ids, mut per_cs) = (vec![], vec![]);
+        while let Some(rest) = lines.optional_field("cs")? {
+            let (id, count) = lines.pair(&rest)?;
+            ids.push(id.to_string());
+            per_cs.push(count);
+        }
+        let mut combinations = BTreeMap::new();
+        while let Some(rest) = lines.optional_field("combination")? {
+            let (bits, count) = lines.pair(&rest)?;
+            combinations.insert(lines.combination(bits, ids.len())?, count);
+        }
+        let mut selected = BTreeMap::new();
+        while let Some(rest) = lines.optional_field("selected")? {
+            let (index, bits) = rest
+                .split_once(' ')
+                .and_then(|(index, bits)| Some((index.parse().ok()?, bits)))
+                .ok_or_else(|| {
+                    lines.error(format!(
+                        "expected an index and a combination, found {rest:?}"
+                    ))
+                })?;
+            selected.insert(index, lines.combination(bits, ids.len())?);
+        }
+        if lines.next()?.is_some() {
+            return Err(lines.error("unexpected line".to_string()));
+        }
+        Ok(Checkpoint {
+            description,
+            range,
+            position,
+            census: Census {
+                ids,
+                programs,
+                degenerate,
+                per_cs,
+                combinations,
+            },
+            selected,
+        })
+    }
+}
+
+fn bits(combination: &[usize]) -> String {
+    combination.iter().map(|b| b.to_string()).collect()
+}
+
+/// Combines the results of shards of the same run into the result of the shards'
+/// ranges together, which have to be adjacent. The order of the shards does not matter.
+pub fn merge_shards(mut shards: Vec<Checkpoint>) -> Result<Checkpoint, MergeError> {
+    if let Some(shard) = shards.iter().find(|shard| !shard.is_complete()) {
+        return Err(MergeError::Incomplete(shard.range.clone(), shard.position));
+    }
+    shards.sort_by_key(|shard| shard.range.start);
+    let mut shards = shards.into_iter();
+    let mut merged = shards.next().ok_or(MergeError::NoShards)?;
+    for shard in shards {
+        if shard.description != merged.description {
+            return Err(MergeError::DifferentRuns(
+                merged.description,
+                shard.description,
+            ));
+        }
+        if shard.range.start != merged.range.end {
+            return Err(MergeError::NotAdjacent(merged.range.end, shard.range.start));
+        }
+        merged.range.end = shard.range.end;
+        merged.position = shard.position;
+        merged.census = merged.census.merge(shard.census);
+        merged.selected.extend(shard.selected);
+    }
+    Ok(merged)
+}
+
+/// Continues the census of the programs of `space` in the range of `start`, which has
+/// to be a checkpoint of a census for the same collision structures and degeneracies,
+/// and selects the non-degenerate programs whose combination `select` accepts. Hands the state to
+/// `save` after every batch of programs, and returns the complete checkpoint, whose
+/// census is the same as the one `census::census` returns for the range.
+pub fn resume_census<F: Field>(
+    space: &ProgramSpace<F>,
+    css: &[DynCollisionStructure],
+    backend: Backend,
+    degenerate: &[DegeneracyKind],
+    start: Checkpoint,
+    select: impl Fn(&[usize]) -> bool + Sync,
+    mut save: impl FnMut(&Checkpoint),
+) -> Checkpoint {
+    resume(space, css, backend, degenerate, start, select, |state| {
+        save(state);
+        true
+    })
+}
+
+/// Like `resume_census`, but stops after the first batch in which a program is
+/// selected, so the first selected program is the first one of the range that `select`
+/// accepts. The returned checkpoint is only complete if that batch was the last one.
+/// If `start` already has a selected program, it is returned as it is.
+pub fn resume_search<F: Field>(
+    space: &ProgramSpace<F>,
+    css: &[DynCollisionStructure],
+    backend: Backend,
+    degenerate: &[DegeneracyKind],
+    start: Checkpoint,
+    select: impl Fn(&[usize]) -> bool + Sync,
+    mut save: impl FnMut(&Checkpoint),
+) -> Checkpoint {
+    if !start.selected.is_empty() {
+        return start;
+    }
+    resume(space, css, backend, degenerate, start, select, |state| {
+        save(state);
+        state.selected.is_empty()
+    })
+}
+
+// Checks the programs batch by batch while `next` accepts the state after a batch.
+fn resume<F: Field>(
+    space: &ProgramSpace<F>,
+    css: &[DynCollisionStructure],
+    backend: Backend,
+    degenerate: &[DegeneracyKind],
+    start: Checkpoint,
+    select: impl Fn(&[usize]) -> bool + Sync,
+    mut next: impl FnMut(&Checkpoint) -> bool,
+) -> Checkpoint {
+    let mut state = start;
+    let remaining = state.range.end.saturating_sub(state.position);
+    let mut programs = space
+        .iter_from(state.position)
+        .take(remaining.try_into().unwrap_or(usize::MAX))
+        .peekable();
+    while programs.peek().is_some() {
+        let batch: Vec<_> = programs.by_ref().take(BATCH).collect();
+        let first = state.position;
+        state.position += batch.len() as u128;
+        let (census, selected) = census_of_batch(batch, css, backend, degenerate, &select);
+        state.census = state.census.merge(census);
+        for (position, combination) in selected {
+            state.selected.insert(first + position as u128, combination);
+        }
+        if !next(&state) {
+            break;
+        }
+    }
+    state
+}
+
+// The lines of a checkpoint, with the number of the current line for errors.
+struct Lines<R> {
+    lines: io::Lines<R>,
+    number: usize,
+    peeked: Option<String>,
+}
+
+impl<R: BufRead> Lines<R> {
+    fn next(&mut self) -> Result<Option<String>, CheckpointError> {
+        if let Some(line) = self.peeked.take() {
+            return Ok(Some(line));
+        }
+        self.number += 1;
+        Ok(self.lines.next().transpose()?)
+    }
+
+    fn error(&self, message: String) -> CheckpointError {
+        CheckpointError::Format {
+            line: self.number,
+            message,
+        }
+    }
+
+    // The rest of the next line if it starts with `name`, otherwise the line is
+    // left for the next call.
+    fn optional_field(&mut self, name: &str) -> Result<Option<String>, CheckpointError> {
+        let Some(line) = self.next()? else {
+            return Ok(None);
+        };
+        match line
+            .strip_prefix(name)
+            .and_then(|rest| rest.strip_prefix(' '))
+        {
+            Some(rest) => Ok(Some(rest.to_string())),
+            None => {
+                self.peeked = Some(line);
+                Ok(None)
+            }
+        }
+    }
+
+    fn field(&mut self, name: &str) -> Result<String, CheckpointError> {
+        match self.optional_field(name)? {
+            Some(rest) => Ok(rest),
+            None => {
+                // The line was not consumed, report it and not the one before.
+                self.peeked = None;
+                Err(self.error(format!("expected {name:?}")))
+            }
+        }
+    }
+
+    fn number<T: std::str::FromStr>(&mut self, name: &str) -> Result<T, CheckpointError> {
+        let rest = self.field(name)?;
+        rest.parse()
+            .map_err(|_| self.error(format!("{rest:?} is not a number")))
+    }
+
+    fn pair<'a>(&self, rest: &'a str) -> Result<(&'a str, usize), CheckpointError> {
+        rest.split_once(' ')
+            .and_then(|(name, count)| Some((name, count.parse().ok()?)))
+            .ok_or_else(|| self.error(format!("expected a name and a count, found {rest:?}")))
+    }
+
+    fn combination(&self, bits: &str, len: usize) -> Result<Vec<usize>, CheckpointError> {
+        bits.chars()
+            .map(|c| match c {
+                '0' => Some(0),
+                '1' => Some(1),
+                _ => None,
+            })
+            .collect::<Option<Vec<usize>>>()
+            .filter(|c| c.len() == len)
+            .ok_or_else(|| self.error(format!("{bits:?} is not a combination")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::census::census;
+    use crate::dynamic::collision_structures;
+    use crate::field::Gf2;
+
+    const RANK: &[DegeneracyKind] = &[DegeneracyKind::RankDeficient];
+
+    fn checkpoint() -> Checkpoint {
+        let css: Vec<_> = collision_structures(2, 1).collect();
+        let mut census = Census::new(&css);
+        census.record(&[1, 0, 0, 1], 2);
+        census.record(&[0, 0, 0, 0], 5);
+        census.record_degenerate(3);
+        Checkpoint {
+            description: "2 queries, exact ranks".to_string(),
+            range: 0..20,
+            position: 10,
+            census,
+            selected: BTreeMap::from([(3, vec![1, 0, 0, 1])]),
+        }
+    }
+
+    #[test]
+    fn check_roundtrip() {
+        let mut text = vec![];
+        checkpoint().write(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("\ncs 10,1,B 2\ncombination 0000 5\n"));
+        assert!(text.ends_with("\nselected 3 1001\n"));
+        let read = Checkpoint::read(text.as_bytes()).unwrap();
+        assert_eq!(read, checkpoint());
+
+        let path = std::env::temp_dir().join("linicrypt-check-roundtrip.checkpoint");
+        checkpoint().save(&path).unwrap();
+        assert_eq!(Checkpoint::load(&path).unwrap(), checkpoint());
+        fs::remove_file(path).unwrap();
+    }
+
+    // The programs with exactly one collision structure.
+    fn select(combination: &[usize]) -> bool {
+        combination.iter().sum::<usize>() == 1
+    }
+
+    #[test]
+    fn check_resume() {
+        let space = ProgramSpace::<Gf2>::new(2, 2, 1).with_last_answer_in_output();
+        let css: Vec<_> = collision_structures(2, 2).collect();
+        let end = space.num_programs();
+
+        // A checkpoint of a census that was interrupted after 1000 programs.
+        let start = Checkpoint::new(String::new(), 0..1000, &css);
+        let mut interrupted =
+            resume_census(&space, &css, Backend::Exact, RANK, start, select, |_| {});
+        interrupted.range = 0..end;
+
+        let mut saved = vec![];
+        let resumed = resume_census(
+            &space,
+            &css,
+            Backend::Exact,
+            RANK,
+            interrupted,
+            select,
+            |c| saved.push(c.position),
+        );
+        assert_eq!(resumed.census, census(&space, &css, Backend::Exact, RANK));
+        assert_eq!(saved, [end]);
+        let selected = space.iter().enumerate().filter_map(|(index, p)| {
+            let combination = crate::census::combination(&p, &css, Backend::Exact);
+            (!p.is_degenerate() && select(&combination)).then_some((index as u128, combination))
+        });
+        assert!(resumed.selected.into_iter().eq(selected));
+
+        let start = Checkpoint::new(String::new(), 0..3000, &css);
+        let part = resume_census(&space, &css, Backend::Exact, RANK, start, select, |_| {});
+        assert!(part.is_complete());
+        assert_eq!(
+            part.census,
+            census(space.iter().take(3000), &css, Backend::Exact, RANK)
+        );
+    }
+
+    #[test]
+    fn check_resume_search() {
+        let space = ProgramSpace::<Gf2>::new(3, 2, 1).with_last_answer_in_output();
+        let css: Vec<_> = collision_structures(2, 2).collect();
+        let none = |combination: &[usize]| combination.iter().all(|&b| b == 0);
+        let first = space.iter().position(|p| {
+            !p.is_degenerate() && none(&crate::census::combination(&p, &css, Backend::Exact))
+        });
+
+        let start = Checkpoint::new(String::new(), 0..space.num_programs(), &css);
+        let mut saved = vec![];
+        let found = resume_search(&space, &css, Backend::Exact, RANK, start, none, |c| {
+            saved.push(c.position)
+        });
+        assert_eq!(
+            found.selected.keys().next(),
+            first.map(|i| i as u128).as_ref()
+        );
+        assert!(!found.is_complete());
+        assert_eq!(saved.last(), Some(&found.position));
+        assert_eq!(found.position % BATCH as u128, 0);
+        assert_eq!(
+            found.census,
+            census(
+                space.iter().take(found.position as usize),
+                &css,
+                Backend::Exact,
+                RANK
+            )
+        );
+    }
+
+    #[test]
+    fn check_merge_shards() {
+        let space = ProgramSpace::<Gf2>::new(2, 2, 1).with_last_answer_in_output();
+        let css: Vec<_> = collision_structures(2, 2).collect();
+        let shard = |range| {
+            let start = Checkpoint::new("run".to_string(), range, &css);
+            resume_census(&space, &css, Backend::Exact, RANK, start, select, |_| {})
+        };
+        let whole = shard(0..space.num_programs());
+        let parts = [
+            shard(1000..space.num_programs()),
+            shard(0..300),
+            shard(300..1000),
+        ];
+        assert_eq!(merge_shards(parts.to_vec()), Ok(whole));
+
+        let gap = vec![parts[0].clone(), parts[1].clone()];
+        assert_eq!(merge_shards(gap), Err(MergeError::NotAdjacent(300, 1000)));
+        let mut other = parts[2].clone();
+        other.description = "other".to_string();
+        let result = merge_shards(vec![parts[1].clone(), other]);
+        assert!(matches!(result, Err(MergeError::DifferentRuns(..))));
+        let mut incomplete = parts[2].clone();
+        incomplete.position = 500;
+        let result = merge_shards(vec![parts[1].clone(), incomplete]);
+        assert_eq!(result, Err(MergeError::Incomplete(300..1000, 500)));
+        assert_eq!(merge_shards(vec![]), Err(MergeError::NoShards));
+    }
+
+    #[test]
+    fn check_format_errors() {
+        let mut text = vec![];
+        checkpoint().write(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        let broken = text.replace("position 10", "position ten");
+        match Checkpoint::read(broken.as_bytes()) {
+            Err(CheckpointError::Format { line: 4, .. }) => {}
+            other => panic!("{other:?}"),
+        }
+        let broken = text.replace("combination 1001", "combination 10x1");
+        match Checkpoint::read(broken.as_bytes()) {
+            Err(CheckpointError::Format { line, .. }) => assert_eq!(line, 12),
+            other => panic!("{other:?}"),
+        }
+        let broken = text.replace("range 0..20", "range 0-20");
+        match Checkpoint::read(broken.as_bytes()) {
+            Err(CheckpointError::Format { line, .. }) => assert_eq!(line, 3),
+            other => panic!("{other:?}"),
+        }
+        let broken = text.replace("range 0..20", "range 0..5");
+        match Checkpoint::read(broken.as_bytes()) {
+            Err(CheckpointError::Format { line, .. }) => assert_eq!(line, 4),
+            other => panic!("{other:?}"),
+        }
+        let broken = text.replace("programs 10", "programs 9");
+        match Checkpoint::read(broken.as_bytes()) {
+            Err(CheckpointError::Format { line, .. }) => assert_eq!(line, 5),
+            other => panic!("{other:?}"),
+        }
+    }
+}
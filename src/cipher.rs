@@ -0,0 +1,168 @@
+//! A lazily sampled ideal cipher over a small field, to run programs and attacks on.
+
+use std::collections::HashMap;
+
+use crate::field::Field;
+use crate::{Operation, Oracle};
+
+/// The SplitMix64 generator. Not suitable for anything but experiments, but it is
+/// small, seedable and reproducible across platforms.
+#[derive(Debug, Clone)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        SplitMix64 { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// A uniformly random field element, up to the negligible bias of the reduction.
+    pub fn element<F: Field>(&mut self) -> F {
+        F::from_u64(self.next_u64() % F::ORDER)
+    }
+}
+
+/// A family of independent uniformly random permutations of `F`, one for every
+/// oracle and key, together with independent random functions for `H` queries.
+///
+/// Values are sampled on first use, so only the queried part of the tables is
+/// stored. The answers only depend on the seed and on the order of the queries.
+#[derive(Debug, Clone)]
+pub struct LazyCipher<F> {
+    rng: SplitMix64,
+    // Indexed by (oracle, key, input).
+    forward: HashMap<(usize, F, F), F>,
+    backward: HashMap<(usize, F, F), F>,
+    hash: HashMap<(usize, F, F), F>,
+    num_queries: usize,
+}
+
+impl<F: Field> LazyCipher<F> {
+    pub fn new(seed: u64) -> Self {
+        LazyCipher {
+            rng: SplitMix64::new(seed),
+            forward: HashMap::new(),
+            backward: HashMap::new(),
+            hash: HashMap::new(),
+            num_queries: 0,
+        }
+    }
+
+    pub fn encrypt(&mut self, oracle: usize, k: F, x: F) -> F {
+        self.num_queries += 1;
+        if let Some(&y) = self.forward.get(&(oracle, k, x)) {
+            return y;
+        }
+        let y = self.sample_unused(|c, y| c.backward.contains_key(&(oracle, k, y)));
+        self.forward.insert((oracle, k, x), y);
+        self.backward.insert((oracle, k, y), x);
+        y
+    }
+
+    pub fn decrypt(&mut self, oracle: usize, k: F, y: F) -> F {
+        self.num_queries += 1;
+        if let Some(&x) = self.backward.get(&(oracle, k, y)) {
+            return x;
+        }
+        let x = self.sample_unused(|c, x| c.forward.contains_key(&(oracle, k, x)));
+        self.forward.insert((oracle, k, x), y);
+        self.backward.insert((oracle, k, y), x);
+        x
+    }
+
+    pub fn hash(&mut self, oracle: usize, k: F, x: F) -> F {
+        self.num_queries += 1;
+        let rng = &mut self.rng;
+        *self
+            .hash
+            .entry((oracle, k, x))
+            .or_insert_with(|| rng.element())
+    }
+
+    /// The number of queries answered so far, including repeated ones.
+    pub fn num_queries(&self) -> usize {
+        self.num_queries
+    }
+
+    // Rejection sampling of a value for which `used` is false, that is one the
+    // permutation for the queried oracle and key does not take yet. Every
+    // permutation has `F::ORDER` values, so this terminates.
+    fn sample_unused(&mut self, used: impl Fn(&Self, F) -> bool) -> F {
+        loop {
+            let candidate = self.rng.element();
+            if !used(self, candidate) {
+                return candidate;
+            }
+        }
+    }
+}
+
+impl<F: Field> Oracle<F> for LazyCipher<F> {
+    fn query(&mut self, oracle: usize, op: Operation, k: F, input: F) -> F {
+        match op {
+            Operation::E => self.encrypt(oracle, k, input),
+            Operation::D => self.decrypt(oracle, k, input),
+            Operation::H => self.hash(oracle, k, input),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::{Gf256, Gf4096};
+    use std::collections::HashSet;
+
+    #[test]
+    fn check_permutation() {
+        let mut cipher = LazyCipher::<Gf256>::new(1);
+        let k = Gf256::from_u64(7);
+        let ys: HashSet<_> = Gf256::elements().map(|x| cipher.encrypt(0, k, x)).collect();
+        assert_eq!(ys.len(), 256);
+        for x in Gf256::elements() {
+            let y = cipher.encrypt(0, k, x);
+            assert_eq!(cipher.decrypt(0, k, y), x);
+        }
+    }
+
+    #[test]
+    fn check_decrypt_first() {
+        let mut cipher = LazyCipher::<Gf4096>::new(2);
+        let (k, y) = (Gf4096::from_u64(1), Gf4096::from_u64(2));
+        let x = cipher.decrypt(3, k, y);
+        assert_eq!(cipher.encrypt(3, k, x), y);
+        assert_eq!(cipher.num_queries(), 2);
+    }
+
+    #[test]
+    fn check_seeded() {
+        let answers = |seed| {
+            let mut cipher = LazyCipher::<Gf4096>::new(seed);
+            Gf4096::elements()
+                .take(16)
+                .map(|x| cipher.query(0, Operation::E, Gf4096::ZERO, x))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(answers(5), answers(5));
+        assert_ne!(answers(5), answers(6));
+    }
+
+    #[test]
+    fn check_oracles_are_independent() {
+        let mut cipher = LazyCipher::<Gf4096>::new(3);
+        let same = Gf4096::elements()
+            .take(64)
+            .filter(|&x| cipher.encrypt(0, x, x) == cipher.encrypt(1, x, x))
+            .count();
+        assert!(same < 4);
+    }
+}
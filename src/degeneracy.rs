@@ -0,0 +1,325 @@
+//! Why a program is degenerate.
+//!
+//! `is_degenerate` only tells whether the rows of a program together have lower rank
+//! than they could. The reasons here are more specific: an input or the answer of a
+//! query that does not matter, a query whose answer an earlier query determines, and
+//! outputs that do not depend on any query. A program can have several of them, and some come without a
+//! rank deficiency, so experiments can choose which kinds they leave out.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::eval::input_coordinates;
+use crate::field::Field;
+use crate::linalg::Backend;
+use crate::validate::{answer_coordinate, validate_program, ValidationError};
+use crate::{Direction, Operation, Program};
+
+/// A reason why a program is degenerate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Degeneracy {
+    /// No query and no output uses the input at this coordinate of the base vector.
+    UnusedInput { coordinate: usize },
+    /// The answer of the query influences no output, neither directly nor through the
+    /// queries after it.
+    UnusedAnswer { query: usize },
+    /// The answer of the query is determined by the query `earlier`, see
+    /// `QueryRows::determines`. This includes a decryption that undoes an earlier
+    /// encryption with the same key, not only literal repetitions.
+    DuplicateQuery { query: usize, earlier: usize },
+    /// The output row only uses inputs, not the answer of any query.
+    OutputWithoutQuery { output: usize },
+    /// The rows of the program together have lower rank than they could, which is
+    /// what `is_degenerate` checks.
+    RankDeficient,
+}
+
+/// The kinds of `Degeneracy`, without the queries and coordinates they are about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DegeneracyKind {
+    UnusedInput,
+    UnusedAnswer,
+    DuplicateQuery,
+    OutputWithoutQuery,
+    RankDeficient,
+}
+
+impl Degeneracy {
+    pub fn kind(&self) -> DegeneracyKind {
+        match self {
+            Degeneracy::UnusedInput { .. } => DegeneracyKind::UnusedInput,
+            Degeneracy::UnusedAnswer { .. } => DegeneracyKind::UnusedAnswer,
+            Degeneracy::DuplicateQuery { .. } => DegeneracyKind::DuplicateQuery,
+            Degeneracy::OutputWithoutQuery { .. } => DegeneracyKind::OutputWithoutQuery,
+            Degeneracy::RankDeficient => DegeneracyKind::RankDeficient,
+        }
+    }
+}
+
+impl fmt::Display for Degeneracy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Degeneracy::UnusedInput { coordinate } => {
+                write!(f, "the input at coordinate {coordinate} is not used")
+            }
+            Degeneracy::UnusedAnswer { query } => {
+                write!(
+                    f,
+                    "the answer of query {query} does not influence the output"
+                )
+            }
+            Degeneracy::DuplicateQuery { query, earlier } => {
+                write!(f, "query {query} repeats query {earlier}")
+            }
+            Degeneracy::OutputWithoutQuery { output } => {
+                write!(f, "output {output} uses no query")
+            }
+            Degeneracy::RankDeficient => write!(f, "the rows are rank deficient"),
+        }
+    }
+}
+
+impl DegeneracyKind {
+    pub const ALL: [DegeneracyKind; 5] = [
+        DegeneracyKind::UnusedInput,
+        DegeneracyKind::UnusedAnswer,
+        DegeneracyKind::DuplicateQuery,
+        DegeneracyKind::OutputWithoutQuery,
+        DegeneracyKind::RankDeficient,
+    ];
+
+    /// The name used on the command line, for example `unused-input`.
+    pub fn name(self) -> &'static str {
+        match self {
+            DegeneracyKind::UnusedInput => "unused-input",
+            DegeneracyKind::UnusedAnswer => "unused-answer",
+            DegeneracyKind::DuplicateQuery => "duplicate-query",
+            DegeneracyKind::OutputWithoutQuery => "output-without-query",
+            DegeneracyKind::RankDeficient => "rank-deficient",
+        }
+    }
+}
+
+impl fmt::Display for DegeneracyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Returned when parsing the name of a `DegeneracyKind` fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDegeneracyKindError(pub String);
+
+impl fmt::Display for ParseDegeneracyKindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<_> = DegeneracyKind::ALL.iter().map(|k| k.name()).collect();
+        write!(f, "{:?} is none of {}", self.0, names.join(", "))
+    }
+}
+
+impl std::error::Error for ParseDegeneracyKindError {}
+
+impl FromStr for DegeneracyKind {
+    type Err = ParseDegeneracyKindError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        DegeneracyKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == name)
+            .ok_or_else(|| ParseDegeneracyKindError(name.to_string()))
+    }
+}
+
+/// All reasons why `p` is degenerate, sorted by kind. The program has to be valid.
+pub fn degeneracies<F: Field>(
+    p: &impl Program<F>,
+    backend: Backend,
+) -> Result<Vec<Degeneracy>, ValidationError> {
+    validate_program(p)?;
+    let used = |coordinate: usize| {
+        (0..p.num_queries()).any(|i| {
+            let q = p.query(i);
+            !q.k[coordinate].is_zero() || !q.x[coordinate].is_zero()
+        }) || (0..p.num_outputs()).any(|j| !p.output(j)[coordinate].is_zero())
+    };
+    let mut reasons = vec![];
+    let inputs = input_coordinates(p).expect("the program is valid");
+    for coordinate in inputs.into_iter().filter(|&l| !used(l)) {
+        reasons.push(Degeneracy::UnusedInput { coordinate });
+    }
+
+    // An answer influences the output if an output uses it, or a later query whose
+    // answer influences the output. So the queries are looked at from the last one.
+    let answers: Vec<usize> = (0..p.num_queries())
+        .map(|i| answer_coordinate(p.query(i).y).unwrap())
+        .collect();
+    let mut influences = vec![false; p.num_queries()];
+    for i in (0..p.num_queries()).rev() {
+        let a = answers[i];
+        influences[i] = (0..p.num_outputs()).any(|j| !p.output(j)[a].is_zero())
+            || (i + 1..p.num_queries()).any(|l| {
+                let q = p.query(l);
+                influences[l] && (!q.k[a].is_zero() || !q.x[a].is_zero())
+            });
+    }
+    for query in (0..p.num_queries()).filter(|&i| !influences[i]) {
+        reasons.push(Degeneracy::UnusedAnswer { query });
+    }
+
+    for query in 0..p.num_queries() {
+        let q = p.query(query);
+        // The direction the program queries the cipher in.
+        let dir = match q.op {
+            Operation::D => Direction::B,
+            Operation::E | Operation::H => Direction::F,
+        };
+        let earlier = (0..query).find(|&j| p.query(j).determines(&q, dir));
+        if let Some(earlier) = earlier {
+            reasons.push(Degeneracy::DuplicateQuery { query, earlier });
+        }
+    }
+
+    for output in 0..p.num_outputs() {
+        let row = p.output(output);
+        if answers.iter().all(|&a| row[a].is_zero()) {
+            reasons.push(Degeneracy::OutputWithoutQuery { output });
+        }
+    }
+    if crate::is_degenerate(p, backend) {
+        reasons.push(Degeneracy::RankDeficient);
+    }
+    Ok(reasons)
+}
+
+/// Whether `p` has a degeneracy of one of the `kinds`. Only rank deficiency is checked
+/// without validating the program first.
+///
+/// # Panics
+/// If another kind is asked for and `p` is not valid.
+pub fn has_degeneracy<F: Field>(
+    p: &impl Program<F>,
+    kinds: &[DegeneracyKind],
+    backend: Backend,
+) -> bool {
+    if kinds.contains(&DegeneracyKind::RankDeficient) && crate::is_degenerate(p, backend) {
+        return true;
+    }
+    if kinds
+        .iter()
+        .all(|&kind| kind == DegeneracyKind::RankDeficient)
+    {
+        return false;
+    }
+    let reasons = degeneracies(p, backend).unwrap_or_else(|e| panic!("{e}"));
+    reasons.iter().any(|reason| kinds.contains(&reason.kind()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::Gf2;
+    use crate::AlgebraicRepresentation;
+    use crate::Operation::*;
+    use Degeneracy::*;
+
+    fn reasons<const BASE: usize, const N: usize, const OUT: usize>(
+        p: &AlgebraicRepresentation<BASE, N, OUT, Gf2>,
+    ) -> Vec<Degeneracy> {
+        degeneracies(p, Backend::Exact).unwrap()
+    }
+
+    #[test]
+    fn check_degeneracies() {
+        // Davies-Meyer with the key and plaintext as inputs is not degenerate.
+        let p = AlgebraicRepresentation::<3, 1, 1, Gf2>::from_coefficients(
+            [0, 1, 1],
+            [(E, [1, 0, 0], [0, 1, 0], [0, 0, 1])],
+        );
+        assert_eq!(reasons(&p), []);
+
+        // The second input is not used, the output is only the first input.
+        let p = AlgebraicRepresentation::<3, 1, 1, Gf2>::from_coefficients(
+            [1, 0, 0],
+            [(E, [1, 0, 0], [1, 0, 0], [0, 0, 1])],
+        );
+        let expected = [
+            UnusedInput { coordinate: 1 },
+            UnusedAnswer { query: 0 },
+            OutputWithoutQuery { output: 0 },
+            RankDeficient,
+        ];
+        assert_eq!(reasons(&p), expected);
+
+        // The second query repeats the first one and its answer is not used, but the
+        // rows still have full rank.
+        let p = AlgebraicRepresentation::<4, 2, 1, Gf2>::from_coefficients(
+            [0, 0, 1, 0],
+            [
+                (E, [1, 0, 0, 0], [0, 1, 0, 0], [0, 0, 1, 0]),
+                (E, [1, 0, 0, 0], [0, 1, 0, 0], [0, 0, 0, 1]),
+            ],
+        );
+        let expected = [
+            UnusedAnswer { query: 1 },
+            DuplicateQuery {
+                query: 1,
+                earlier: 0,
+            },
+        ];
+        assert_eq!(reasons(&p), expected);
+
+        // The answer of the first query only influences the output through the second.
+        let p = AlgebraicRepresentation::<4, 2, 1, Gf2>::from_coefficients(
+            [0, 0, 0, 1],
+            [
+                (E, [1, 0, 0, 0], [0, 1, 0, 0], [0, 0, 1, 0]),
+                (E, [1, 0, 0, 0], [0, 0, 1, 0], [0, 0, 0, 1]),
+            ],
+        );
+        assert!(!reasons(&p).contains(&UnusedAnswer { query: 0 }));
+
+        // y0 = E(a, b) and y1 = D(a, y0), which is b again.
+        let p = AlgebraicRepresentation::<4, 2, 1, Gf2>::from_coefficients(
+            [0, 0, 1, 1],
+            [
+                (E, [1, 0, 0, 0], [0, 1, 0, 0], [0, 0, 1, 0]),
+                (D, [1, 0, 0, 0], [0, 0, 1, 0], [0, 0, 0, 1]),
+            ],
+        );
+        let duplicate = DuplicateQuery {
+            query: 1,
+            earlier: 0,
+        };
+        assert!(reasons(&p).contains(&duplicate));
+        // With the second query to another cipher it is not determined by the first.
+        assert!(!reasons(&p.with_oracles([0, 1])).contains(&duplicate));
+    }
+
+    #[test]
+    fn check_output_without_query() {
+        // Two outputs, y0 and a + b, the second one does not use the query.
+        let p = AlgebraicRepresentation::<3, 1, 2, Gf2>::from_output_coefficients(
+            [[0, 0, 1], [1, 1, 0]],
+            [(E, [1, 0, 0], [0, 1, 0], [0, 0, 1])],
+        );
+        assert_eq!(reasons(&p), [OutputWithoutQuery { output: 1 }]);
+    }
+
+    #[test]
+    fn check_kinds() {
+        for kind in DegeneracyKind::ALL {
+            assert_eq!(kind.name().parse(), Ok(kind));
+        }
+        assert!("unused".parse::<DegeneracyKind>().is_err());
+
+        let p = AlgebraicRepresentation::<3, 1, 1, Gf2>::from_coefficients(
+            [1, 0, 1],
+            [(E, [1, 0, 0], [1, 0, 0], [0, 0, 1])],
+        );
+        assert_eq!(reasons(&p), [UnusedInput { coordinate: 1 }, RankDeficient]);
+        let kinds = [DegeneracyKind::UnusedInput];
+        assert!(has_degeneracy(&p, &kinds, Backend::Exact));
+        let kinds = [DegeneracyKind::DuplicateQuery, DegeneracyKind::UnusedAnswer];
+        assert!(!has_degeneracy(&p, &kinds, Backend::Exact));
+    }
+}
@@ -0,0 +1,532 @@
+//! A small text language for Linicrypt programs.
+//!
+//! ```text
+//! input a b c
+//! y0 = E(b, a)
+//! y1 = E(c + y0, b)
+//! out y0 + y1
+//! ```
+//!
+//! Statements are separated by new lines or `;`, and `#` starts a comment. `input`
+//! names the inputs, `y = E(k, x)` makes a query with key `k` and input `x` and names
+//! its answer `y`, and `out` adds output rows, separated by commas. The operation is
+//! `E`, `D` or `H`, followed by the index of the oracle if it is not `0`, like `E1`.
+//! The rows are sums of names with optional coefficients, like `a + 2*y0`, or `0`. A
+//! coefficient has to be below the order of the field.
+//! A name has to be defined before it is used, so every parsed program is valid.
+//!
+//! The base vector of a parsed program has the inputs first, in the order they are
+//! named, and then the answers of the queries in order, like the programs of
+//! `space::ProgramSpace`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use itertools::Itertools;
+
+use crate::dynamic::{DynAlgebraicRepresentation, DynConstraint, ShapeMismatch};
+use crate::eval::input_coordinates;
+use crate::field::Field;
+use crate::validate::{answer_coordinate, validate_program, ValidationError};
+use crate::{AlgebraicRepresentation, Operation, Program};
+
+/// Returned when a program can not be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The text at the (1-based) line and column is not what was expected.
+    Syntax {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// The program does not have the shape of the `AlgebraicRepresentation` it is read as.
+    Shape(ShapeMismatch),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Syntax {
+                line,
+                column,
+                message,
+            } => write!(f, "line {line}, column {column}: {message}"),
+            ParseError::Shape(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Name(String),
+    Number(u64),
+    Symbol(char),
+    /// The end of a statement, a new line or `;`.
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Name(name) => write!(f, "{name:?}"),
+            Token::Number(n) => write!(f, "{n}"),
+            Token::Symbol(c) => write!(f, "{:?}", c.to_string()),
+            Token::End => write!(f, "the end of the statement"),
+        }
+    }
+}
+
+// A token with the line and column it starts at.
+struct Located {
+    token: Token,
+    line: usize,
+    column: usize,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Located>, ParseError> {
+    let mut tokens = vec![];
+    for (l, line) in text.lines().enumerate() {
+        let mut chars = line.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            let column = line[..i].chars().count() + 1;
+            let token = match c {
+                '#' => break,
+                _ if c.is_whitespace() => continue,
+                ';' => Token::End,
+                '=' | '(' | ')' | ',' | '+' | '-' | '*' => Token::Symbol(c),
+                _ if c.is_ascii_alphanumeric() || c == '_' => {
+                    let mut word = c.to_string();
+                    while let Some(&(_, c)) = chars.peek() {
+                        if !(c.is_ascii_alphanumeric() || c == '_') {
+                            break;
+                        }
+                        word.push(c);
+                        chars.next();
+                    }
+                    if !c.is_ascii_digit() {
+                        Token::Name(word)
+                    } else {
+                        let number = word.parse().map_err(|_| ParseError::Syntax {
+                            line: l + 1,
+                            column,
+                            message: format!("{word:?} is not a number"),
+                        })?;
+                        Token::Number(number)
+                    }
+                }
+                _ => {
+                    return Err(ParseError::Syntax {
+                        line: l + 1,
+                        column,
+                        message: format!("unexpected character {c:?}"),
+                    })
+                }
+            };
+            tokens.push(Located {
+                token,
+                line: l + 1,
+                column,
+            });
+        }
+        tokens.push(Located {
+            token: Token::End,
+            line: l + 1,
+            column: line.chars().count() + 1,
+        });
+    }
+    Ok(tokens)
+}
+
+// A name in the program, an input or the answer of a query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Var {
+    Input(usize),
+    Answer(usize),
+}
+
+// A row as the coefficients of names, before the number of inputs is known.
+type Terms<F> = Vec<(F, Var)>;
+
+struct Parser<F> {
+    tokens: Vec<Located>,
+    position: usize,
+    names: HashMap<String, Var>,
+    inputs: usize,
+    queries: Vec<(Operation, usize, Terms<F>, Terms<F>)>,
+    outputs: Vec<Terms<F>>,
+}
+
+impl<F: Field> Parser<F> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|t| &t.token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.position += 1;
+        token
+    }
+
+    // An error at the token before `next`.
+    fn error(&self, message: String) -> ParseError {
+        self.error_at(self.position.saturating_sub(1), message)
+    }
+
+    // An error at the token with the given index, or at the end of the text.
+    fn error_at(&self, index: usize, message: String) -> ParseError {
+        let (line, column) = match self.tokens.get(index).or(self.tokens.last()) {
+            Some(t) => (t.line, t.column),
+            None => (1, 1),
+        };
+        ParseError::Syntax {
+            line,
+            column,
+            message,
+        }
+    }
+
+    fn unexpected(&self, token: Option<Token>, expected: &str) -> ParseError {
+        match token {
+            Some(token) => self.error(format!("expected {expected}, found {token}")),
+            None => self.error(format!("expected {expected} before the end of the text")),
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), ParseError> {
+        match self.next() {
+            Some(Token::Symbol(c)) if c == symbol => Ok(()),
+            token => Err(self.unexpected(token, &format!("{:?}", symbol.to_string()))),
+        }
+    }
+
+    // Defines the name of the token with the given index.
+    fn define(&mut self, name: String, var: Var, index: usize) -> Result<(), ParseError> {
+        if name == "input" || name == "out" {
+            return Err(self.error_at(index, format!("{name:?} can not be used as a name")));
+        }
+        if self.names.contains_key(&name) {
+            return Err(self.error_at(index, format!("{name:?} is already defined")));
+        }
+        self.names.insert(name, var);
+        Ok(())
+    }
+
+    fn program(&mut self) -> Result<(), ParseError> {
+        while let Some(token) = self.next() {
+            match token {
+                Token::End => continue,
+                Token::Name(name) if name == "input" => {
+                    while let Some(Token::Name(name)) = self.peek().cloned() {
+                        self.define(name, Var::Input(self.inputs), self.position)?;
+                        self.next();
+                        self.inputs += 1;
+                    }
+                }
+                Token::Name(name) if name == "out" => loop {
+                    let row = self.row()?;
+                    self.outputs.push(row);
+                    if self.peek() != Some(&Token::Symbol(',')) {
+                        break;
+                    }
+                    self.next();
+                },
+                Token::Name(name) => self.query(name)?,
+                token => return Err(self.unexpected(Some(token), "a statement")),
+            }
+            match self.next() {
+                Some(Token::End) | None => {}
+                token => return Err(self.unexpected(token, "the end of the statement")),
+            }
+        }
+        if self.outputs.is_empty() {
+            return Err(self.error("the program has no output".to_string()));
+        }
+        Ok(())
+    }
+
+    // `<name> = <operation>(<k>, <x>)`, after the name.
+    fn query(&mut self, name: String) -> Result<(), ParseError> {
+        let name_index = self.position - 1;
+        self.expect('=')?;
+        let (op, oracle) = match self.next() {
+            Some(Token::Name(op)) => operation(&op)
+                .ok_or_else(|| self.error(format!("{op:?} is not an operation like E or D1")))?,
+            token => return Err(self.unexpected(token, "an operation")),
+        };
+        self.expect('(')?;
+        let k = self.row()?;
+        self.expect(',')?;
+        let x = self.row()?;
+        self.expect(')')?;
+        // The answer is only defined after the query, so it can not be used in it.
+        self.define(name, Var::Answer(self.queries.len()), name_index)?;
+        self.queries.push((op, oracle, k, x));
+        Ok(())
+    }
+
+    // A sum of terms, or `0`.
+    fn row(&mut self) -> Result<Terms<F>, ParseError> {
+        let mut terms = vec![];
+        let mut sign = F::ONE;
+        if let Some(Token::Symbol('-')) = self.peek() {
+            self.next();
+            sign = -F::ONE;
+        }
+        loop {
+            if let Some(term) = self.term()? {
+                terms.push((sign * term.0, term.1));
+            }
+            sign = match self.peek() {
+                Some(Token::Symbol('+')) => F::ONE,
+                Some(Token::Symbol('-')) => -F::ONE,
+                _ => return Ok(terms),
+            };
+            self.next();
+        }
+    }
+
+    // `<name>`, `<coefficient>*<name>`, or `0`, which is no term.
+    fn term(&mut self) -> Result<Option<(F, Var)>, ParseError> {
+        let coefficient = match self.peek() {
+            Some(&Token::Number(n)) => {
+                self.next();
+                if n >= F::ORDER {
+                    let message = format!("{n} is not an element of a field of order {}", F::ORDER);
+                    return Err(self.error(message));
+                }
+                if n == 0 && !matches!(self.peek(), Some(Token::Symbol('*'))) {
+                    return Ok(None);
+                }
+                self.expect('*')?;
+                F::from_u64(n)
+            }
+            _ => F::ONE,
+        };
+        match self.next() {
+            Some(Token::Name(name)) => match self.names.get(&name) {
+                Some(&var) => Ok(Some((coefficient, var))),
+                None => Err(self.error(format!("{name:?} is not defined"))),
+            },
+            token => Err(self.unexpected(token, "a name")),
+        }
+    }
+
+    fn finish(self) -> DynAlgebraicRepresentation<F> {
+        let base = self.inputs + self.queries.len();
+        let inputs = self.inputs;
+        let row = |terms: &Terms<F>| {
+            let mut row = vec![F::ZERO; base];
+            for &(coefficient, var) in terms {
+                let column = match var {
+                    Var::Input(i) => i,
+                    Var::Answer(i) => inputs + i,
+                };
+                row[column] = row[column] + coefficient;
+            }
+            row
+        };
+        let constraints = self
+            .queries
+            .iter()
+            .enumerate()
+            .map(|(i, (op, oracle, k, x))| {
+                let mut y = vec![F::ZERO; base];
+                y[inputs + i] = F::ONE;
+                DynConstraint {
+                    op: *op,
+                    oracle: *oracle,
+                    k: row(k),
+                    x: row(x),
+                    y,
+                }
+            })
+            .collect();
+        DynAlgebraicRepresentation {
+            base,
+            m: self.outputs.iter().map(row).collect(),
+            constraints,
+        }
+    }
+}
+
+// The operation and oracle of a name like `E` or `D1`.
+fn operation(name: &str) -> Option<(Operation, usize)> {
+    let op = match name.get(..1)? {
+        "E" => Operation::E,
+        "D" => Operation::D,
+        "H" => Operation::H,
+        _ => return None,
+    };
+    let oracle = match &name[1..] {
+        "" => 0,
+        digits if digits.bytes().all(|b| b.is_ascii_digit()) => digits.parse().ok()?,
+        _ => return None,
+    };
+    Some((op, oracle))
+}
+
+/// Reads a program of any shape.
+pub fn parse<F: Field>(text: &str) -> Result<DynAlgebraicRepresentation<F>, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        position: 0,
+        names: HashMap::new(),
+        inputs: 0,
+        queries: vec![],
+        outputs: vec![],
+    };
+    parser.program()?;
+    Ok(parser.finish())
+}
+
+impl<F: Field> FromStr for DynAlgebraicRepresentation<F> {
+    type Err = ParseError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        parse(text)
+    }
+}
+
+impl<const BASE: usize, const N: usize, const OUT: usize, F: Field> FromStr
+    for AlgebraicRepresentation<BASE, N, OUT, F>
+{
+    type Err = ParseError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        AlgebraicRepresentation::try_from(parse::<F>(text)?).map_err(ParseError::Shape)
+    }
+}
+
+/// Writes `p` in the text language, one statement per line. The inputs are named `a`,
+/// `b` and so on, or `x0`, `x1` and so on if there are more than 26, and the answer of
+/// query `i` is named `y<i>`. Reading the text gives back `p`, up to the order of the
+/// coordinates of the base vector if `p` does not have the inputs first.
+pub fn to_text<F: Field>(p: &impl Program<F>) -> Result<String, ValidationError> {
+    validate_program(p)?;
+    let inputs = input_coordinates(p).expect("the program is valid");
+    let mut names = vec![String::new(); p.base()];
+    for (n, &column) in inputs.iter().enumerate() {
+        names[column] = if inputs.len() <= 26 {
+            char::from(b'a' + n as u8).to_string()
+        } else {
+            format!("x{n}")
+        };
+    }
+    // The names in the order they are defined in, with their coordinates.
+    let mut order = inputs.clone();
+    for i in 0..p.num_queries() {
+        let column = answer_coordinate(p.query(i).y).unwrap();
+        names[column] = format!("y{i}");
+        order.push(column);
+    }
+    let row = |row: &[F]| {
+        let terms = order
+            .iter()
+            .filter(|&&column| !row[column].is_zero())
+            .map(|&column| match row[column] {
+                c if c == F::ONE => names[column].clone(),
+                c => format!("{}*{}", c.to_u64(), names[column]),
+            })
+            .join(" + ");
+        if terms.is_empty() {
+            "0".to_string()
+        } else {
+            terms
+        }
+    };
+
+    let mut lines = vec![];
+    if !inputs.is_empty() {
+        lines.push(format!(
+            "input {}",
+            inputs.iter().map(|&c| &names[c]).join(" ")
+        ));
+    }
+    for i in 0..p.num_queries() {
+        let q = p.query(i);
+        let oracle = if q.oracle == 0 {
+            String::new()
+        } else {
+            q.oracle.to_string()
+        };
+        let y = &names[answer_coordinate(q.y).unwrap()];
+        let (k, x) = (row(q.k), row(q.x));
+        lines.push(format!("{y} = {:?}{oracle}({k}, {x})", q.op));
+    }
+    let outputs = (0..p.num_outputs()).map(|j| row(&p.output(j))).join(", ");
+    lines.push(format!("out {outputs}"));
+    Ok(lines.join("\n") + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::{Gf2, Gf3};
+    use crate::space::ProgramSpace;
+    use Operation::*;
+
+    #[test]
+    fn check_parse() {
+        let text = "input a b c; y0 = E(b, a); y1 = E(c + y0, b); out y0 + y1";
+        let p: AlgebraicRepresentation<5, 2, 1> = text.parse().unwrap();
+        let expected = AlgebraicRepresentation::<5, 2, 1>::from_coefficients(
+            [0, 0, 0, 1, 1],
+            [
+                (E, [0, 1, 0, 0, 0], [1, 0, 0, 0, 0], [0, 0, 0, 1, 0]),
+                (E, [0, 0, 1, 1, 0], [0, 1, 0, 0, 0], [0, 0, 0, 0, 1]),
+            ],
+        );
+        assert_eq!(p, expected);
+
+        let text = "
+            # Two outputs over GF(3), a decryption with the second cipher.
+            input a
+            t = D1(0, a)
+            out 2*t - a, a + a
+        ";
+        let p = parse::<Gf3>(text).unwrap();
+        let row = |r: [u64; 2]| r.map(Gf3::from_u64).to_vec();
+        assert_eq!(p.m, [row([2, 2]), row([2, 0])]);
+        assert_eq!((p.constraints[0].op, p.constraints[0].oracle), (D, 1));
+        assert_eq!(p.constraints[0].k, row([0, 0]));
+        assert_eq!(p.validate(), Ok(()));
+    }
+
+    #[test]
+    fn check_errors() {
+        let error = |text: &str| match parse::<Gf2>(text) {
+            Err(ParseError::Syntax { line, column, .. }) => (line, column),
+            other => panic!("{other:?}"),
+        };
+        assert_eq!(error("input a\ny = E(a, y)\nout y"), (2, 10));
+        assert_eq!(error("input a b\ny = E(a b)"), (2, 9));
+        assert_eq!(error("input a\ny = F(a, a)\nout y"), (2, 5));
+        assert_eq!(error("input a a"), (1, 9));
+        assert_eq!(error("input a\nout a $"), (2, 7));
+        assert_eq!(error("input a\ny = E(a, a"), (2, 11));
+        assert_eq!(error("input a"), (1, 8));
+        assert_eq!(error("input a\nout 2*a"), (2, 5));
+        assert!(parse::<Gf3>("input a\nout 2*a").is_ok());
+        assert!(parse::<Gf3>("input a\nout a + 3*a").is_err());
+
+        let result = "input a; out a".parse::<AlgebraicRepresentation<2, 1, 1>>();
+        assert!(matches!(result, Err(ParseError::Shape(_))));
+    }
+
+    #[test]
+    fn check_roundtrip() {
+        let text = "input a b c\ny0 = E(b, a)\ny1 = E(c + y0, b)\nout y0 + y1\n";
+        let p = parse::<Gf2>(text).unwrap();
+        assert_eq!(to_text(&p).unwrap(), text);
+
+        let space = ProgramSpace::<Gf3>::new(1, 2, 2).with_operations(&[(E, 0), (H, 2)]);
+        for index in (0..space.num_programs()).step_by(9973) {
+            let p = space.unrank(index).unwrap();
+            let text = to_text(&p).unwrap();
+            assert_eq!(parse::<Gf3>(&text), Ok(p), "{text}");
+        }
+    }
+}
@@ -0,0 +1,622 @@
+//! Programs whose shape is only known at runtime.
+//!
+//! `AlgebraicRepresentation` needs a monomorphised instance for every combination
+//! of `BASE`, `N` and `OUT`. The types here store their rows in `Vec`s instead and
+//! convert from and to the const-generic versions.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+use itertools::{iproduct, Itertools};
+use na::*;
+use nalgebra as na;
+
+use crate::field::{Field, Gf2};
+use crate::linalg::Backend;
+use crate::validate::{validate_program, ValidationError};
+use crate::{
+    AlgebraicRepresentation, CollisionStructure, CollisionStructureTrait, Constraint,
+    CsExplanation, Direction, Operation, Program, QueryRows,
+};
+
+/// The number of base variables, queries and output rows of a program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Shape {
+    pub base: usize,
+    pub queries: usize,
+    pub outputs: usize,
+}
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "base {}, {} queries, {} outputs",
+            self.base, self.queries, self.outputs
+        )
+    }
+}
+
+/// Returned when converting a `DynAlgebraicRepresentation` into an
+/// `AlgebraicRepresentation` of a different shape. If the shape matches but a row
+/// does not have `base` entries, `found.base` is the length of that row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShapeMismatch {
+    pub expected: Shape,
+    pub found: Shape,
+}
+
+impl fmt::Display for ShapeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "expected a program with {}, found {}",
+            self.expected, self.found
+        )
+    }
+}
+
+impl std::error::Error for ShapeMismatch {}
+
+/// Returned when converting a `DynCollisionStructure` into a `CollisionStructure`
+/// with a different number of queries or different queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsSizeMismatch {
+    /// The number of queries and of different queries.
+    pub expected: (usize, usize),
+    pub found: (usize, usize),
+}
+
+impl fmt::Display for CsSizeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "expected a collision structure for {} queries with {} different ones, found one for {} with {}",
+            self.expected.0, self.expected.1, self.found.0, self.found.1
+        )
+    }
+}
+
+impl std::error::Error for CsSizeMismatch {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DynConstraint<F: Field = Gf2> {
+    pub op: Operation,
+    pub oracle: usize,
+    pub k: Vec<F>,
+    pub x: Vec<F>,
+    pub y: Vec<F>,
+}
+
+/// Runtime-sized counterpart of `AlgebraicRepresentation`. Every row of `m` is an
+/// output, and all rows have length `base`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DynAlgebraicRepresentation<F: Field = Gf2> {
+    pub base: usize,
+    pub m: Vec<Vec<F>>,
+    pub constraints: Vec<DynConstraint<F>>,
+}
+
+type RawDynConstraint = (Operation, Vec<u64>, Vec<u64>, Vec<u64>);
+
+impl<F: Field> DynAlgebraicRepresentation<F> {
+    /// The coefficients are mapped to field elements with `Field::from_u64`.
+    /// The base is taken from the length of the first output row.
+    ///
+    /// # Panics
+    /// If the program is not valid, see `try_new`.
+    pub fn new(m: Vec<Vec<u64>>, cs: Vec<RawDynConstraint>) -> Self {
+        Self::try_new(m, cs).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Like `new`, but returns an error if there is no output row, the rows have
+    /// different lengths or the program is not valid otherwise.
+    pub fn try_new(m: Vec<Vec<u64>>, cs: Vec<RawDynConstraint>) -> Result<Self, ValidationError> {
+        let base = m.first().ok_or(ValidationError::NoOutputs)?.len();
+        let row = |entries: Vec<u64>| -> Vec<F> { entries.into_iter().map(F::from_u64).collect() };
+        let constraints = cs
+            .into_iter()
+            .map(|(op, k, x, y)| DynConstraint {
+                op,
+                oracle: 0,
+                k: row(k),
+                x: row(x),
+                y: row(y),
+            })
+            .collect();
+        let p = DynAlgebraicRepresentation {
+            base,
+            m: m.into_iter().map(row).collect(),
+            constraints,
+        };
+        p.validate().map(|()| p)
+    }
+
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        validate_program(self)
+    }
+
+    /// Assigns query `i` to the oracle `oracles[i]`, see `AlgebraicRepresentation::with_oracles`.
+    pub fn with_oracles(mut self, oracles: &[usize]) -> Self {
+        assert_eq!(oracles.len(), self.constraints.len());
+        for (c, &oracle) in self.constraints.iter_mut().zip(oracles) {
+            c.oracle = oracle;
+        }
+        self
+    }
+
+    pub fn shape(&self) -> Shape {
+        Shape {
+            base: self.base,
+            queries: self.constraints.len(),
+            outputs: self.m.len(),
+        }
+    }
+
+    /// `cs` has to be a collision structure for `self.constraints.len()` queries.
+    pub fn has_cs(&self, cs: &impl CollisionStructureTrait) -> bool {
+        self.has_cs_with(cs, Backend::default())
+    }
+    pub fn has_cs_with(&self, cs: &impl CollisionStructureTrait, backend: Backend) -> bool {
+        assert_eq!(
+            cs.num_queries(),
+            self.constraints.len(),
+            "collision structure for the wrong number of queries"
+        );
+        crate::check_cs(self, cs, backend)
+    }
+    pub fn explain_cs(&self, cs: &impl CollisionStructureTrait) -> CsExplanation<F> {
+        self.explain_cs_with(cs, Backend::default())
+    }
+    pub fn explain_cs_with(
+        &self,
+        cs: &impl CollisionStructureTrait,
+        backend: Backend,
+    ) -> CsExplanation<F> {
+        assert_eq!(
+            cs.num_queries(),
+            self.constraints.len(),
+            "collision structure for the wrong number of queries"
+        );
+        crate::explain_cs(self, cs, backend)
+    }
+    pub fn is_degenerate(&self) -> bool {
+        self.is_degenerate_with(Backend::default())
+    }
+    pub fn is_degenerate_with(&self, backend: Backend) -> bool {
+        crate::is_degenerate(self, backend)
+    }
+}
+
+impl<F: Field> Program<F> for DynAlgebraicRepresentation<F> {
+    fn base(&self) -> usize {
+        self.base
+    }
+    fn num_queries(&self) -> usize {
+        self.constraints.len()
+    }
+    fn num_outputs(&self) -> usize {
+        self.m.len()
+    }
+    fn query(&self, i: usize) -> QueryRows<'_, F> {
+        let c = &self.constraints[i];
+        QueryRows {
+            op: c.op,
+            oracle: c.oracle,
+            k: &c.k,
+            x: &c.x,
+            y: &c.y,
+        }
+    }
+    fn output(&self, j: usize) -> Cow<'_, [F]> {
+        Cow::Borrowed(&self.m[j])
+    }
+}
+
+/// Runtime-sized counterpart of `CollisionStructure`, for enumerating collision
+/// structures of any number of queries.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DynCollisionStructure {
+    permutation: Vec<usize>,
+    cs_type: Vec<Direction>,
+}
+
+impl DynCollisionStructure {
+    /// The last `cs_type.len()` queries of `permutation` are the different ones.
+    ///
+    /// # Panics
+    /// If the structure is not valid, see `try_new`.
+    pub fn new(permutation: Vec<usize>, cs_type: Vec<Direction>) -> Self {
+        Self::try_new(permutation, cs_type).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Returns an error if `permutation` is not a permutation of `0..permutation.len()`,
+    /// or if `cs_type` is empty or longer than `permutation`.
+    pub fn try_new(
+        permutation: Vec<usize>,
+        cs_type: Vec<Direction>,
+    ) -> Result<Self, ValidationError> {
+        let cs = DynCollisionStructure {
+            permutation,
+            cs_type,
+        };
+        cs.validate().map(|()| cs)
+    }
+
+    /// The corresponding structure for the program that makes the query `order[j]`
+    /// as its query `j`, see `AlgebraicRepresentation::reorder_queries`. The same
+    /// queries are sorted, like in `collision_structures`.
+    pub fn reorder_queries(&self, order: &[usize]) -> Self {
+        assert_eq!(order.len(), self.num_queries());
+        let mut new_index = vec![0; order.len()];
+        for (j, &i) in order.iter().enumerate() {
+            new_index[i] = j;
+        }
+        let mut permutation: Vec<_> = self.permutation.iter().map(|&i| new_index[i]).collect();
+        permutation[..self.same().len()].sort_unstable();
+        DynCollisionStructure::new(permutation, self.cs_type.clone())
+    }
+}
+
+/// Returned when parsing a collision structure id fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseCsError {
+    /// The id does not have the form `<permutation>,<number of same>,<directions>`,
+    /// for example `01,0,FB`.
+    Format(String),
+    Invalid(ValidationError),
+}
+
+impl fmt::Display for ParseCsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseCsError::Format(id) => {
+                write!(f, "{id:?} is not a collision structure id like \"01,0,FB\"")
+            }
+            ParseCsError::Invalid(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ParseCsError {}
+
+/// Parses the ids returned by `CollisionStructureTrait::id`. The permutation is
+/// written with one digit per query, so only structures for up to ten queries
+/// can be parsed.
+impl FromStr for DynCollisionStructure {
+    type Err = ParseCsError;
+
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        let format_error = || ParseCsError::Format(id.to_string());
+        let (permutation, same, types) = id.split(',').collect_tuple().ok_or_else(format_error)?;
+        let permutation = permutation
+            .chars()
+            .map(|c| c.to_digit(10).map(|d| d as usize))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(format_error)?;
+        let same: usize = same.parse().map_err(|_| format_error())?;
+        let cs_type = types
+            .chars()
+            .map(|c| match c {
+                'F' => Some(Direction::F),
+                'B' => Some(Direction::B),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(format_error)?;
+        if same + cs_type.len() != permutation.len() {
+            return Err(format_error());
+        }
+        DynCollisionStructure::try_new(permutation, cs_type).map_err(ParseCsError::Invalid)
+    }
+}
+
+impl CollisionStructureTrait for DynCollisionStructure {
+    fn permutation(&self) -> &[usize] {
+        &self.permutation
+    }
+    fn types(&self) -> &[Direction] {
+        &self.cs_type
+    }
+}
+
+/// All collision structures for `num_queries` queries with `diff` different ones.
+///
+/// The order of the same queries does not matter for `has_cs`, so only one
+/// permutation is yielded for every set of same queries, with the set sorted.
+/// Every ordering of the different queries is combined with every choice of
+/// directions, for `n` queries this gives `binomial(n, diff) * diff! * 2^diff`
+/// structures. There are none if `diff` is 0 or larger than `num_queries`.
+pub fn collision_structures(
+    num_queries: usize,
+    diff: usize,
+) -> impl Iterator<Item = DynCollisionStructure> {
+    use Direction::*;
+    let valid = (1..=num_queries).contains(&diff);
+    (0..num_queries)
+        .combinations(num_queries.saturating_sub(diff))
+        .filter(move |_| valid)
+        .flat_map(move |same| {
+            let different: Vec<_> = (0..num_queries).filter(|i| !same.contains(i)).collect();
+            let orders = different.into_iter().permutations(diff);
+            let types = (0..diff).map(|_| [F, B]).multi_cartesian_product();
+            iproduct!(orders, types).map(move |(order, cs_type)| {
+                let permutation = same.iter().copied().chain(order).collect();
+                DynCollisionStructure::new(permutation, cs_type)
+            })
+        })
+}
+
+/// All collision structures for `num_queries` queries, for every number of
+/// different queries from 1 to `num_queries`.
+pub fn all_collision_structures(num_queries: usize) -> impl Iterator<Item = DynCollisionStructure> {
+    (1..=num_queries).flat_map(move |diff| collision_structures(num_queries, diff))
+}
+
+impl<const N: usize, const DIFF: usize> From<&CollisionStructure<N, DIFF>>
+    for DynCollisionStructure
+{
+    fn from(cs: &CollisionStructure<N, DIFF>) -> Self {
+        DynCollisionStructure::new(cs.permutation.to_vec(), cs.cs_type.to_vec())
+    }
+}
+
+impl<const N: usize, const DIFF: usize> TryFrom<&DynCollisionStructure>
+    for CollisionStructure<N, DIFF>
+{
+    type Error = CsSizeMismatch;
+
+    fn try_from(cs: &DynCollisionStructure) -> Result<Self, Self::Error> {
+        let found = (cs.permutation.len(), cs.cs_type.len());
+        if found != (N, DIFF) {
+            return Err(CsSizeMismatch {
+                expected: (N, DIFF),
+                found,
+            });
+        }
+        Ok(CollisionStructure {
+            permutation: std::array::from_fn(|i| cs.permutation[i]),
+            cs_type: std::array::from_fn(|i| cs.cs_type[i]),
+        })
+    }
+}
+
+impl<const BASE: usize, F: Field> From<&Constraint<BASE, F>> for DynConstraint<F> {
+    fn from(c: &Constraint<BASE, F>) -> Self {
+        DynConstraint {
+            op: c.op,
+            oracle: c.oracle,
+            k: c.k.as_slice().to_vec(),
+            x: c.x.as_slice().to_vec(),
+            y: c.y.as_slice().to_vec(),
+        }
+    }
+}
+
+impl<const BASE: usize, const N: usize, const OUT: usize, F: Field>
+    From<&AlgebraicRepresentation<BASE, N, OUT, F>> for DynAlgebraicRepresentation<F>
+{
+    fn from(p: &AlgebraicRepresentation<BASE, N, OUT, F>) -> Self {
+        DynAlgebraicRepresentation {
+            base: BASE,
+            m: (0..OUT).map(|j| p.output(j).into_owned()).collect(),
+            constraints: p.constraints.iter().map(DynConstraint::from).collect(),
+        }
+    }
+}
+
+impl<const BASE: usize, const N: usize, const OUT: usize, F: Field>
+    From<AlgebraicRepresentation<BASE, N, OUT, F>> for DynAlgebraicRepresentation<F>
+{
+    fn from(p: AlgebraicRepresentation<BASE, N, OUT, F>) -> Self {
+        DynAlgebraicRepresentation::from(&p)
+    }
+}
+
+impl<const BASE: usize, const N: usize, const OUT: usize, F: Field>
+    TryFrom<&DynAlgebraicRepresentation<F>> for AlgebraicRepresentation<BASE, N, OUT, F>
+{
+    type Error = ShapeMismatch;
+
+    fn try_from(p: &DynAlgebraicRepresentation<F>) -> Result<Self, Self::Error> {
+        let expected = Shape {
+            base: BASE,
+            queries: N,
+            outputs: OUT,
+        };
+        if p.shape() != expected {
+            return Err(ShapeMismatch {
+                expected,
+                found: p.shape(),
+            });
+        }
+        // The fields are public, so the rows can have other lengths than `base`.
+        let queries = p.constraints.iter().flat_map(|c| [&c.k, &c.x, &c.y]);
+        if let Some(row) = p.m.iter().chain(queries).find(|row| row.len() != BASE) {
+            let found = Shape {
+                base: row.len(),
+                ..expected
+            };
+            return Err(ShapeMismatch { expected, found });
+        }
+        let row = |entries: &[F]| RowSVector::<F, BASE>::from_row_slice(entries);
+        let constraints: [Constraint<BASE, F>; N] = std::array::from_fn(|i| {
+            let c = &p.constraints[i];
+            Constraint {
+                op: c.op,
+                oracle: c.oracle,
+                k: row(&c.k),
+                x: row(&c.x),
+                y: row(&c.y),
+            }
+        });
+        Ok(AlgebraicRepresentation {
+            m: SMatrix::from_fn(|j, col| p.m[j][col]),
+            constraints,
+        })
+    }
+}
+
+impl<const BASE: usize, const N: usize, const OUT: usize, F: Field>
+    TryFrom<DynAlgebraicRepresentation<F>> for AlgebraicRepresentation<BASE, N, OUT, F>
+{
+    type Error = ShapeMismatch;
+
+    fn try_from(p: DynAlgebraicRepresentation<F>) -> Result<Self, Self::Error> {
+        AlgebraicRepresentation::try_from(&p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Direction::*;
+    use crate::Operation::*;
+
+    fn example() -> AlgebraicRepresentation<5, 2, 1> {
+        AlgebraicRepresentation::new(
+            [0, 0, 1, 0, 1],
+            [
+                (E, [0, 1, 0, 0, 0], [1, 0, 0, 0, 0], [0, 0, 0, 1, 0]),
+                (E, [1, 1, 1, 1, 0], [0, 1, 0, 1, 0], [0, 0, 0, 0, 1]),
+            ],
+        )
+    }
+
+    #[test]
+    fn check_roundtrip() {
+        let p = example();
+        let dynamic = DynAlgebraicRepresentation::from(&p);
+        assert_eq!(
+            dynamic.shape(),
+            Shape {
+                base: 5,
+                queries: 2,
+                outputs: 1
+            }
+        );
+        assert_eq!(dynamic.m, vec![[0, 0, 1, 0, 1].map(Gf2::from).to_vec()]);
+
+        let back = AlgebraicRepresentation::<5, 2, 1>::try_from(&dynamic).unwrap();
+        assert_eq!(back.m, p.m);
+        assert_eq!(back.constraints, p.constraints);
+
+        let wrong = AlgebraicRepresentation::<5, 1, 1>::try_from(&dynamic);
+        assert_eq!(wrong.unwrap_err().found, dynamic.shape());
+
+        let mut short = dynamic.clone();
+        short.constraints[1].x.pop();
+        let wrong = AlgebraicRepresentation::<5, 2, 1>::try_from(&short);
+        assert_eq!(wrong.unwrap_err().found.base, 4);
+        let mut short = dynamic.clone();
+        short.m[0].pop();
+        assert!(AlgebraicRepresentation::<5, 2, 1>::try_from(&short).is_err());
+    }
+
+    #[test]
+    fn check_dynamic_matches_static() {
+        let p = example();
+        let dynamic = DynAlgebraicRepresentation::from(&p);
+        for permutation in [[0, 1], [1, 0]] {
+            for cs_type in [[F, F], [F, B], [B, F], [B, B]] {
+                let cs = CollisionStructure {
+                    permutation,
+                    cs_type,
+                };
+                assert_eq!(dynamic.has_cs(&cs), p.has_cs(&cs));
+                let dyn_cs = DynCollisionStructure::from(&cs);
+                assert_eq!(dyn_cs.id(), cs.id());
+                assert_eq!(dyn_cs.types(), cs.directions());
+                assert_eq!(p.has_cs(&dyn_cs), p.has_cs(&cs));
+                assert_eq!(dynamic.has_cs(&dyn_cs), p.has_cs(&cs));
+                assert_eq!(CollisionStructure::try_from(&dyn_cs), Ok(cs));
+                let wrong = CollisionStructure::<2, 1>::try_from(&dyn_cs).unwrap_err();
+                assert_eq!(wrong.found, (2, 2));
+            }
+            for cs_type in [[F], [B]] {
+                let cs = CollisionStructure {
+                    permutation,
+                    cs_type,
+                };
+                assert_eq!(dynamic.has_cs(&cs), p.has_cs(&cs));
+            }
+        }
+        assert_eq!(dynamic.is_degenerate(), p.is_degenerate());
+    }
+
+    #[test]
+    fn check_new_from_coefficients() {
+        let dynamic = DynAlgebraicRepresentation::<Gf2>::new(
+            vec![vec![0, 0, 1, 0, 1]],
+            vec![
+                (
+                    E,
+                    vec![0, 1, 0, 0, 0],
+                    vec![1, 0, 0, 0, 0],
+                    vec![0, 0, 0, 1, 0],
+                ),
+                (
+                    E,
+                    vec![1, 1, 1, 1, 0],
+                    vec![0, 1, 0, 1, 0],
+                    vec![0, 0, 0, 0, 1],
+                ),
+            ],
+        );
+        assert_eq!(dynamic, DynAlgebraicRepresentation::from(example()));
+
+        let two_ciphers = dynamic.with_oracles(&[0, 1]);
+        let back = AlgebraicRepresentation::<5, 2, 1>::try_from(&two_ciphers).unwrap();
+        assert_eq!(back.constraints.map(|c| c.oracle), [0, 1]);
+    }
+
+    #[test]
+    #[should_panic(expected = "not a permutation")]
+    fn check_dyn_cs_needs_permutation() {
+        DynCollisionStructure::new(vec![0, 0], vec![F]);
+    }
+
+    #[test]
+    fn check_number_of_collision_structures() {
+        assert_eq!(collision_structures(2, 2).count(), 8);
+        assert_eq!(collision_structures(2, 1).count(), 4);
+        assert_eq!(collision_structures(2, 0).count(), 0);
+        assert_eq!(collision_structures(1, 2).count(), 0);
+        assert_eq!(collision_structures(0, 0).count(), 0);
+        // 3 * 1 * 2 + 3 * 2 * 4 + 1 * 6 * 8
+        assert_eq!(all_collision_structures(3).count(), 78);
+        let ids: std::collections::HashSet<_> =
+            all_collision_structures(4).map(|cs| cs.id()).collect();
+        assert_eq!(ids.len(), 4 * 2 + 6 * 2 * 4 + 4 * 6 * 8 + 24 * 16);
+    }
+
+    #[test]
+    fn check_same_prefix_is_sorted() {
+        for cs in all_collision_structures(4) {
+            assert!(cs.same().windows(2).all(|w| w[0] < w[1]));
+        }
+    }
+
+    #[test]
+    fn check_parse_cs_id() {
+        for cs in all_collision_structures(3) {
+            assert_eq!(cs.id().parse(), Ok(cs));
+        }
+        let parse = |id: &str| id.parse::<DynCollisionStructure>();
+        assert_eq!(
+            parse("01,0,FX"),
+            Err(ParseCsError::Format("01,0,FX".into()))
+        );
+        assert_eq!(
+            parse("01,1,FB"),
+            Err(ParseCsError::Format("01,1,FB".into()))
+        );
+        assert_eq!(
+            parse("00,1,F"),
+            Err(ParseCsError::Invalid(ValidationError::NotAPermutation {
+                permutation: vec![0, 0]
+            }))
+        );
+    }
+}
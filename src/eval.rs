@@ -0,0 +1,203 @@
+//! Running programs on concrete inputs.
+//!
+//! The base vector `v` of an execution starts with the inputs at every coordinate
+//! that is not the output of a query. The queries are then made in order, query `i`
+//! asks the oracle for `op(k·v, x·v)` and writes the answer to the coordinate of its
+//! `y` row. The output of the program is `m·v`.
+
+use std::fmt;
+
+use crate::field::Field;
+use crate::validate::{answer_coordinate, validate_program, ValidationError};
+use crate::{Operation, Oracle, Program};
+
+/// An oracle query made while running a program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OracleCall<F> {
+    pub query: usize,
+    pub oracle: usize,
+    pub op: Operation,
+    pub k: F,
+    pub input: F,
+    pub output: F,
+}
+
+/// Everything computed while running a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transcript<F> {
+    pub input: Vec<F>,
+    /// The base vector, with the inputs and the answers of all queries.
+    pub base: Vec<F>,
+    pub output: Vec<F>,
+    /// The queries in program order.
+    pub queries: Vec<OracleCall<F>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalError {
+    /// The program is not valid, for example because a query uses the output of a
+    /// later query.
+    Invalid(ValidationError),
+    /// The input has a different length than the number of program inputs.
+    WrongInputLength { expected: usize, found: usize },
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::Invalid(e) => e.fmt(f),
+            EvalError::WrongInputLength { expected, found } => {
+                write!(f, "expected {expected} inputs, found {found}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+impl From<ValidationError> for EvalError {
+    fn from(e: ValidationError) -> Self {
+        EvalError::Invalid(e)
+    }
+}
+
+/// Runs `p` on `input`, asking `oracle` for the answers to its queries.
+pub fn evaluate<F: Field>(
+    p: &impl Program<F>,
+    oracle: &mut impl Oracle<F>,
+    input: &[F],
+) -> Result<Transcript<F>, EvalError> {
+    let inputs = input_coordinates(p)?;
+    if inputs.len() != input.len() {
+        return Err(EvalError::WrongInputLength {
+            expected: inputs.len(),
+            found: input.len(),
+        });
+    }
+    let mut v = vec![F::ZERO; p.base()];
+    for (&j, &value) in inputs.iter().zip(input) {
+        v[j] = value;
+    }
+    let mut queries = Vec::with_capacity(p.num_queries());
+    for i in 0..p.num_queries() {
+        let c = p.query(i);
+        // The program is valid, so the output row is a unit vector and the key and
+        // input only use coordinates that are already computed.
+        let j = answer_coordinate(c.y).unwrap();
+        let (k, x) = (dot(c.k, &v), dot(c.x, &v));
+        v[j] = oracle.query(c.oracle, c.op, k, x);
+        queries.push(OracleCall {
+            query: i,
+            oracle: c.oracle,
+            op: c.op,
+            k,
+            input: x,
+            output: v[j],
+        });
+    }
+    Ok(Transcript {
+        input: input.to_vec(),
+        output: (0..p.num_outputs())
+            .map(|j| dot(&p.output(j), &v))
+            .collect(),
+        base: v,
+        queries,
+    })
+}
+
+/// The coordinates of the base vector that are inputs of the program, in order.
+pub fn input_coordinates<F: Field>(p: &impl Program<F>) -> Result<Vec<usize>, EvalError> {
+    validate_program(p)?;
+    let mut is_input = vec![true; p.base()];
+    for i in 0..p.num_queries() {
+        is_input[answer_coordinate(p.query(i).y).unwrap()] = false;
+    }
+    Ok((0..p.base()).filter(|&j| is_input[j]).collect())
+}
+
+pub(crate) fn dot<F: Field>(row: &[F], v: &[F]) -> F {
+    row.iter().zip(v).fold(F::ZERO, |acc, (&a, &b)| acc + a * b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cipher::LazyCipher;
+    use crate::field::Gf256;
+    use crate::AlgebraicRepresentation;
+    use Operation::*;
+
+    #[test]
+    fn check_davies_meyer() {
+        // y = E(a, b), output y + b
+        let p = AlgebraicRepresentation::<3, 1, 1, Gf256>::from_coefficients(
+            [0, 1, 1],
+            [(E, [1, 0, 0], [0, 1, 0], [0, 0, 1])],
+        );
+        let mut cipher = LazyCipher::new(4);
+        let (a, b) = (Gf256::from_u64(3), Gf256::from_u64(9));
+        let t = evaluate(&p, &mut cipher, &[a, b]).unwrap();
+        let y = cipher.encrypt(0, a, b);
+        assert_eq!(t.base, [a, b, y]);
+        assert_eq!(t.output, [y + b]);
+        assert_eq!(
+            t.queries,
+            [OracleCall {
+                query: 0,
+                oracle: 0,
+                op: E,
+                k: a,
+                input: b,
+                output: y
+            }]
+        );
+    }
+
+    #[test]
+    fn check_inputs_between_outputs() {
+        // y0 = D(a, b), y1 = E(y0, c), output y1, with c after y0 in the base vector.
+        let p = AlgebraicRepresentation::<5, 2, 1, Gf256>::from_coefficients(
+            [0, 0, 0, 0, 1],
+            [
+                (D, [1, 0, 0, 0, 0], [0, 1, 0, 0, 0], [0, 0, 1, 0, 0]),
+                (E, [0, 0, 1, 0, 0], [0, 0, 0, 1, 0], [0, 0, 0, 0, 1]),
+            ],
+        );
+        assert_eq!(input_coordinates(&p), Ok(vec![0, 1, 3]));
+        let mut cipher = LazyCipher::new(0);
+        let input = [1, 2, 3].map(Gf256::from_u64);
+        let t = evaluate(&p, &mut cipher, &input).unwrap();
+        assert_eq!(t.queries[1].k, t.queries[0].output);
+        assert_eq!(t.queries[1].input, input[2]);
+        assert_eq!(
+            evaluate(&p, &mut cipher, &input[..2]),
+            Err(EvalError::WrongInputLength {
+                expected: 3,
+                found: 2
+            })
+        );
+    }
+
+    #[test]
+    fn check_ill_formed_program() {
+        let mut cipher = LazyCipher::<Gf256>::new(0);
+        // y0 = E(a, b), y1 = E(a, c), output y1
+        let mut p = AlgebraicRepresentation::<5, 2, 1, Gf256>::from_coefficients(
+            [0, 0, 0, 0, 1],
+            [
+                (E, [1, 0, 0, 0, 0], [0, 1, 0, 0, 0], [0, 0, 0, 1, 0]),
+                (E, [1, 0, 0, 0, 0], [0, 0, 1, 0, 0], [0, 0, 0, 0, 1]),
+            ],
+        );
+        // Now the first query uses y1 as its key.
+        p.constraints[0].k = p.constraints[1].y;
+        assert_eq!(
+            evaluate(&p, &mut cipher, &[Gf256::ONE; 3]),
+            Err(EvalError::Invalid(ValidationError::UsesLaterOutput {
+                query: 0,
+                coordinate: 4
+            }))
+        );
+        assert_eq!(cipher.num_queries(), 0);
+    }
+}
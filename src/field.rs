@@ -0,0 +1,325 @@
+// Arithmetic in characteristic two is done with bit operations.
+#![allow(clippy::suspicious_arithmetic_impl)]
+
+use std::fmt;
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+use std::ops::{Add, Mul, Neg, Sub};
+
+/// A finite field the entries of a program (and the values it computes on) live in.
+///
+/// Elements are identified with the integers `0..ORDER` through `from_u64` and
+/// `to_u64`. For `Gf2` and `Fp` this is the usual representative, for `Gf2k` it is
+/// the polynomial with the bits of the integer as coefficients.
+pub trait Field:
+    Copy
+    + Eq
+    + Ord
+    + Hash
+    + Debug
+    + Display
+    + Send
+    + Sync
+    + 'static
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Neg<Output = Self>
+{
+    const ORDER: u64;
+    const ZERO: Self;
+    const ONE: Self;
+
+    /// Maps `n` to a field element, reducing it if it is not smaller than `ORDER`.
+    fn from_u64(n: u64) -> Self;
+    fn to_u64(self) -> u64;
+    fn inverse(self) -> Option<Self>;
+
+    fn is_zero(self) -> bool {
+        self == Self::ZERO
+    }
+
+    /// All elements of the field, in the order of `to_u64`.
+    fn elements() -> std::iter::Map<std::ops::Range<u64>, fn(u64) -> Self> {
+        (0..Self::ORDER).map(Self::from_u64 as fn(u64) -> Self)
+    }
+}
+
+/// The field with two elements. This is the field all experiments used originally.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Gf2(u8);
+
+impl Field for Gf2 {
+    const ORDER: u64 = 2;
+    const ZERO: Self = Gf2(0);
+    const ONE: Self = Gf2(1);
+
+    fn from_u64(n: u64) -> Self {
+        Gf2((n & 1) as u8)
+    }
+    fn to_u64(self) -> u64 {
+        self.0 as u64
+    }
+    fn inverse(self) -> Option<Self> {
+        (self.0 == 1).then_some(self)
+    }
+}
+
+impl From<u8> for Gf2 {
+    fn from(n: u8) -> Self {
+        Gf2::from_u64(n as u64)
+    }
+}
+
+impl Add for Gf2 {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Gf2(self.0 ^ rhs.0)
+    }
+}
+
+impl Sub for Gf2 {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        self + rhs
+    }
+}
+
+impl Mul for Gf2 {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        Gf2(self.0 & rhs.0)
+    }
+}
+
+impl Neg for Gf2 {
+    type Output = Self;
+    fn neg(self) -> Self {
+        self
+    }
+}
+
+/// The prime field GF(P). `P` has to be a prime, this is not checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Fp<const P: u64>(u64);
+
+pub type Gf3 = Fp<3>;
+pub type Gf5 = Fp<5>;
+
+impl<const P: u64> Fp<P> {
+    fn pow(self, mut exp: u64) -> Self {
+        let mut base = self;
+        let mut acc = Self::ONE;
+        while exp > 0 {
+            if exp & 1 == 1 {
+                acc = acc * base;
+            }
+            base = base * base;
+            exp >>= 1;
+        }
+        acc
+    }
+}
+
+impl<const P: u64> Field for Fp<P> {
+    const ORDER: u64 = P;
+    const ZERO: Self = Fp(0);
+    const ONE: Self = Fp(1);
+
+    fn from_u64(n: u64) -> Self {
+        Fp(n % P)
+    }
+    fn to_u64(self) -> u64 {
+        self.0
+    }
+    fn inverse(self) -> Option<Self> {
+        (!self.is_zero()).then(|| self.pow(P - 2))
+    }
+}
+
+impl<const P: u64> Add for Fp<P> {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Fp(((self.0 as u128 + rhs.0 as u128) % P as u128) as u64)
+    }
+}
+
+impl<const P: u64> Sub for Fp<P> {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        self + (-rhs)
+    }
+}
+
+impl<const P: u64> Mul for Fp<P> {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        Fp(((self.0 as u128 * rhs.0 as u128) % P as u128) as u64)
+    }
+}
+
+impl<const P: u64> Neg for Fp<P> {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Fp((P - self.0) % P)
+    }
+}
+
+/// The binary extension field GF(2^K), with elements represented as polynomials
+/// over GF(2) modulo `POLY`. `POLY` includes the leading term `x^K` and has to be
+/// irreducible, this is not checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Gf2k<const K: u32, const POLY: u64>(u64);
+
+/// GF(2^8) with the AES polynomial x^8 + x^4 + x^3 + x + 1.
+pub type Gf256 = Gf2k<8, 0x11b>;
+/// GF(2^12) with the polynomial x^12 + x^3 + 1.
+pub type Gf4096 = Gf2k<12, 0x1009>;
+
+impl<const K: u32, const POLY: u64> Gf2k<K, POLY> {
+    const MASK: u64 = (1 << K) - 1;
+
+    /// Multiplication by x, the "doubling" used in many double block length designs.
+    pub fn double(self) -> Self {
+        let shifted = self.0 << 1;
+        if shifted >> K == 1 {
+            Gf2k(shifted ^ POLY)
+        } else {
+            Gf2k(shifted)
+        }
+    }
+
+    fn pow(self, mut exp: u64) -> Self {
+        let mut base = self;
+        let mut acc = Self::ONE;
+        while exp > 0 {
+            if exp & 1 == 1 {
+                acc = acc * base;
+            }
+            base = base * base;
+            exp >>= 1;
+        }
+        acc
+    }
+}
+
+impl<const K: u32, const POLY: u64> Field for Gf2k<K, POLY> {
+    const ORDER: u64 = 1 << K;
+    const ZERO: Self = Gf2k(0);
+    const ONE: Self = Gf2k(1);
+
+    fn from_u64(n: u64) -> Self {
+        Gf2k(n & Self::MASK)
+    }
+    fn to_u64(self) -> u64 {
+        self.0
+    }
+    fn inverse(self) -> Option<Self> {
+        (!self.is_zero()).then(|| self.pow(Self::ORDER - 2))
+    }
+}
+
+impl<const K: u32, const POLY: u64> Add for Gf2k<K, POLY> {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Gf2k(self.0 ^ rhs.0)
+    }
+}
+
+impl<const K: u32, const POLY: u64> Sub for Gf2k<K, POLY> {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        self + rhs
+    }
+}
+
+impl<const K: u32, const POLY: u64> Mul for Gf2k<K, POLY> {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        let mut acc = Self::ZERO;
+        let mut a = self;
+        let mut b = rhs.0;
+        while b > 0 {
+            if b & 1 == 1 {
+                acc = acc + a;
+            }
+            a = a.double();
+            b >>= 1;
+        }
+        acc
+    }
+}
+
+impl<const K: u32, const POLY: u64> Neg for Gf2k<K, POLY> {
+    type Output = Self;
+    fn neg(self) -> Self {
+        self
+    }
+}
+
+impl fmt::Display for Gf2 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl<const P: u64> fmt::Display for Fp<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl<const K: u32, const POLY: u64> fmt::Display for Gf2k<K, POLY> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_field_axioms<F: Field>() {
+        for a in F::elements() {
+            assert_eq!(a + F::ZERO, a);
+            assert_eq!(a * F::ONE, a);
+            assert_eq!((a + F::ONE) - F::ONE, a);
+            assert_eq!(a + (-a), F::ZERO);
+            assert_eq!(F::from_u64(a.to_u64()), a);
+            match a.inverse() {
+                Some(inv) => assert_eq!(a * inv, F::ONE),
+                None => assert_eq!(a, F::ZERO),
+            }
+        }
+    }
+
+    #[test]
+    fn check_small_fields() {
+        check_field_axioms::<Gf2>();
+        check_field_axioms::<Gf3>();
+        check_field_axioms::<Gf5>();
+        check_field_axioms::<Fp<251>>();
+    }
+
+    #[test]
+    fn check_binary_extension_fields() {
+        check_field_axioms::<Gf2k<2, 0b111>>();
+        check_field_axioms::<Gf256>();
+        check_field_axioms::<Gf4096>();
+    }
+
+    #[test]
+    fn check_gf256_arithmetic() {
+        // The example from FIPS-197, section 4.2.
+        assert_eq!(
+            Gf256::from_u64(0x57) * Gf256::from_u64(0x83),
+            Gf256::from_u64(0xc1)
+        );
+        assert_eq!(Gf256::from_u64(0x57).double(), Gf256::from_u64(0xae));
+        assert_eq!(Gf256::from_u64(0x80).double(), Gf256::from_u64(0x1b));
+        assert_eq!(
+            Gf256::from_u64(0x57) * Gf256::from_u64(2),
+            Gf256::from_u64(0xae)
+        );
+    }
+}
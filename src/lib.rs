@@ -1,47 +1,264 @@
 use na::*;
 use nalgebra as na;
 
+pub mod attack;
+pub mod canonical;
+pub mod census;
+pub mod checkpoint;
+pub mod cipher;
+pub mod degeneracy;
+pub mod dsl;
+pub mod dynamic;
+pub mod eval;
+pub mod field;
+pub mod linalg;
 pub mod print_grid;
+pub mod space;
+pub mod validate;
 
-const EPSILON: f64 = 0.0001;
+use std::borrow::Cow;
 
-#[derive(Debug, Clone, PartialEq)]
+use itertools::Itertools;
+
+use field::{Field, Gf2};
+use linalg::{Backend, BackendSpan, Span};
+use validate::{validate_cs, validate_program, ValidationError};
+
+/// The kind of oracle query a constraint describes.
+///
+/// In a constraint `x` is always the input and `y` the output of the query. For `E`
+/// this means `E(k, x) = y`, for `D` it means `D(k, x) = y`, that is `E(k, y) = x`.
+/// `H` is a random oracle query `H(k, x) = y` on the two blocks `k` and `x`. A hash of
+/// a single block is written with a zero row as `k`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Operation {
     E,
     D,
+    H,
+}
+
+/// Answers queries to the ideal primitives a program uses.
+pub trait Oracle<F> {
+    /// With `op == E` encrypts `input` under the key `k` with the cipher `oracle`,
+    /// with `D` decrypts it and with `H` hashes `(k, input)` with the random oracle `oracle`.
+    fn query(&mut self, oracle: usize, op: Operation, k: F, input: F) -> F;
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct Constraint<const BASE: usize> {
+/// `oracle` identifies which of several independent ideal primitives is queried.
+/// Programs with a single cipher (or random oracle) use `0` everywhere.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Constraint<const BASE: usize, F: Field = Gf2> {
     pub op: Operation,
-    pub k: RowSVector<u8, BASE>,
-    pub x: RowSVector<u8, BASE>,
-    pub y: RowSVector<u8, BASE>,
+    pub oracle: usize,
+    pub k: RowSVector<F, BASE>,
+    pub x: RowSVector<F, BASE>,
+    pub y: RowSVector<F, BASE>,
 }
 
-#[derive(Debug)]
-pub struct AlgebraicRepresentation<const BASE: usize, const N: usize, const OUT: usize> {
-    pub m: SMatrix<u8, OUT, BASE>,
-    pub constraints: [Constraint<BASE>; N],
+/// The entries of the matrices are elements of the field `F`, which defaults to GF(2).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AlgebraicRepresentation<
+    const BASE: usize,
+    const N: usize,
+    const OUT: usize,
+    F: Field = Gf2,
+> {
+    pub m: SMatrix<F, OUT, BASE>,
+    pub constraints: [Constraint<BASE, F>; N],
 }
 
-type RawConstraint<const BASE: usize> = (Operation, [u8; BASE], [u8; BASE], [u8; BASE]);
+type RawConstraint<const BASE: usize, T = u8> = (Operation, [T; BASE], [T; BASE], [T; BASE]);
 impl<const BASE: usize, const N: usize> AlgebraicRepresentation<BASE, N, 1> {
+    /// # Panics
+    /// If the program is not valid, see `validate`.
     pub fn new(m: [u8; BASE], cs: [RawConstraint<BASE>; N]) -> Self {
+        Self::with_outputs([m], cs)
+    }
+}
+
+impl<const BASE: usize, const N: usize, const OUT: usize> AlgebraicRepresentation<BASE, N, OUT> {
+    /// Like `new`, for programs with one output per row of `m`.
+    pub fn with_outputs(m: [[u8; BASE]; OUT], cs: [RawConstraint<BASE>; N]) -> Self {
+        Self::from_output_coefficients(
+            m.map(|row| row.map(u64::from)),
+            cs.map(|(op, k, x, y)| (op, k.map(u64::from), x.map(u64::from), y.map(u64::from))),
+        )
+    }
+}
+
+impl<const BASE: usize, const N: usize, F: Field> AlgebraicRepresentation<BASE, N, 1, F> {
+    /// Like `new`, but for any field. The coefficients are mapped to field elements
+    /// with `Field::from_u64`.
+    pub fn from_coefficients(m: [u64; BASE], cs: [RawConstraint<BASE, u64>; N]) -> Self {
+        Self::from_output_coefficients([m], cs)
+    }
+}
+
+impl<const BASE: usize, const N: usize, const OUT: usize, F: Field>
+    AlgebraicRepresentation<BASE, N, OUT, F>
+{
+    /// The constructor all others go through, see `try_from_output_coefficients`.
+    ///
+    /// # Panics
+    /// If the program is not valid.
+    pub fn from_output_coefficients(
+        m: [[u64; BASE]; OUT],
+        cs: [RawConstraint<BASE, u64>; N],
+    ) -> Self {
+        Self::try_from_output_coefficients(m, cs).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Builds the program with the rows of `m` as outputs and checks that it is valid.
+    /// The coefficients are mapped to field elements with `Field::from_u64`, every
+    /// query goes to oracle `0`.
+    pub fn try_from_output_coefficients(
+        m: [[u64; BASE]; OUT],
+        cs: [RawConstraint<BASE, u64>; N],
+    ) -> Result<Self, ValidationError> {
+        let row = |entries: [u64; BASE]| RowSVector::from_row_slice(&entries.map(F::from_u64));
         let constraints = cs.map(|(op, k, x, y)| Constraint {
             op,
-            k: RowSVector::from_row_slice(&k),
-            x: RowSVector::from_row_slice(&x),
-            y: RowSVector::from_row_slice(&y),
+            oracle: 0,
+            k: row(k),
+            x: row(x),
+            y: row(y),
         });
-        AlgebraicRepresentation::<BASE, N, 1> {
-            m: SMatrix::from_row_slice(&m),
+        let p = AlgebraicRepresentation {
+            m: SMatrix::from_fn(|j, col| F::from_u64(m[j][col])),
             constraints,
+        };
+        p.validate().map(|()| p)
+    }
+
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        validate_program(self)
+    }
+}
+
+impl<const BASE: usize, const N: usize, const OUT: usize, F: Field>
+    AlgebraicRepresentation<BASE, N, OUT, F>
+{
+    /// Assigns query `i` to the oracle `oracles[i]`. The constructors put every
+    /// query on oracle `0`.
+    pub fn with_oracles(mut self, oracles: [usize; N]) -> Self {
+        for (c, oracle) in self.constraints.iter_mut().zip(oracles) {
+            c.oracle = oracle;
+        }
+        self
+    }
+
+    /// The same program with every coefficient mapped to `G` through `to_u64` and
+    /// `from_u64`. For programs over `Gf2` this embeds them into any other field,
+    /// for example to run them on a cipher over `Gf4096`.
+    pub fn lift<G: Field>(&self) -> AlgebraicRepresentation<BASE, N, OUT, G> {
+        let lift_row = |row: &RowSVector<F, BASE>| row.map(|e| G::from_u64(e.to_u64()));
+        AlgebraicRepresentation {
+            m: self.m.map(|e| G::from_u64(e.to_u64())),
+            constraints: self.constraints.each_ref().map(|c| Constraint {
+                op: c.op,
+                oracle: c.oracle,
+                k: lift_row(&c.k),
+                x: lift_row(&c.x),
+                y: lift_row(&c.y),
+            }),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The rows of a single query, borrowed from a program.
+#[derive(Debug, Clone, Copy)]
+pub struct QueryRows<'a, F> {
+    pub op: Operation,
+    pub oracle: usize,
+    pub k: &'a [F],
+    pub x: &'a [F],
+    pub y: &'a [F],
+}
+
+impl<'a, F> QueryRows<'a, F> {
+    /// The key, plaintext and ciphertext rows, independent of the direction the program
+    /// queried the cipher in. For random oracle queries these are `k`, `x` and `y`.
+    pub fn cipher_rows(&self) -> (&'a [F], &'a [F], &'a [F]) {
+        match self.op {
+            Operation::E | Operation::H => (self.k, self.x, self.y),
+            Operation::D => (self.k, self.y, self.x),
+        }
+    }
+
+    /// Whether the answer to this query determines the answer to `other` in direction
+    /// `dir`. This is the case if both go to the same oracle and the rows the
+    /// adversary fixes in direction `dir` are identical.
+    ///
+    /// The rows are compared exactly, not modulo the fixed span: rows that only differ
+    /// by a fixed vector still make different queries for almost all inputs, so the
+    /// second answer stays free. Only identical rows are the same query for every input.
+    pub fn determines(&self, other: &QueryRows<'_, F>, dir: Direction) -> bool
+    where
+        F: PartialEq,
+    {
+        let same_primitive = (self.op == Operation::H) == (other.op == Operation::H);
+        if self.oracle != other.oracle || !same_primitive {
+            return false;
+        }
+        let (k, plain, cipher) = self.cipher_rows();
+        let (other_k, other_plain, other_cipher) = other.cipher_rows();
+        match dir {
+            Direction::F => k == other_k && plain == other_plain,
+            Direction::B => k == other_k && cipher == other_cipher,
+        }
+    }
+
+    /// Whether an adversary can make this query in direction `dir`. A random oracle
+    /// can only be queried forward.
+    pub fn allows(&self, dir: Direction) -> bool {
+        self.op != Operation::H || dir == Direction::F
+    }
+}
+
+/// Read access to a program, independent of whether its shape is known at compile time.
+/// The analysis is written against this trait, so it is shared by
+/// `AlgebraicRepresentation` and `dynamic::DynAlgebraicRepresentation`.
+pub trait Program<F: Field> {
+    fn base(&self) -> usize;
+    fn num_queries(&self) -> usize;
+    fn num_outputs(&self) -> usize;
+    fn query(&self, i: usize) -> QueryRows<'_, F>;
+    fn output(&self, j: usize) -> Cow<'_, [F]>;
+}
+
+impl<const BASE: usize, const N: usize, const OUT: usize, F: Field> Program<F>
+    for AlgebraicRepresentation<BASE, N, OUT, F>
+{
+    fn base(&self) -> usize {
+        BASE
+    }
+    fn num_queries(&self) -> usize {
+        N
+    }
+    fn num_outputs(&self) -> usize {
+        OUT
+    }
+    fn query(&self, i: usize) -> QueryRows<'_, F> {
+        let c = &self.constraints[i];
+        QueryRows {
+            op: c.op,
+            oracle: c.oracle,
+            k: c.k.as_slice(),
+            x: c.x.as_slice(),
+            y: c.y.as_slice(),
+        }
+    }
+    fn output(&self, j: usize) -> Cow<'_, [F]> {
+        // The matrix is stored column major, so only a single row is contiguous.
+        if OUT == 1 {
+            Cow::Borrowed(self.m.as_slice())
+        } else {
+            Cow::Owned(self.m.row(j).iter().copied().collect())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Direction {
     F,
     B,
@@ -57,132 +274,362 @@ impl fmt::Display for Direction {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct CollisionStructure<const N: usize, const DIFF: usize> {
     pub permutation: [usize; N],
     pub cs_type: [Direction; DIFF],
 }
 
-// TODO This will probably be needed to generate collision structures dynamically
+impl<const N: usize, const DIFF: usize> CollisionStructure<N, DIFF> {
+    /// The last `DIFF` queries of `permutation` are the different ones.
+    ///
+    /// # Panics
+    /// If the structure is not valid, see `try_new`.
+    pub fn new(permutation: [usize; N], cs_type: [Direction; DIFF]) -> Self {
+        Self::try_new(permutation, cs_type).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Returns an error if `permutation` is not a permutation of `0..N`, or if `DIFF`
+    /// is 0 or larger than `N`.
+    pub fn try_new(
+        permutation: [usize; N],
+        cs_type: [Direction; DIFF],
+    ) -> Result<Self, ValidationError> {
+        let cs = CollisionStructure {
+            permutation,
+            cs_type,
+        };
+        cs.validate().map(|()| cs)
+    }
+
+    /// The directions of the different queries, the same as `types`.
+    pub fn directions(&self) -> &[Direction] {
+        self.types()
+    }
+}
+
+/// A collision structure of any size. The first `num_queries() - types().len()`
+/// queries of the permutation are the same in both executions, the remaining
+/// ones differ and are made in the given directions, starting with i^*.
+///
+/// Implemented by the const-generic `CollisionStructure` and by
+/// `dynamic::DynCollisionStructure`, whose size is only known at runtime.
 pub trait CollisionStructureTrait {
-    fn same(&self) -> &[usize];
-    fn different(&self) -> DifferentIter;
+    fn permutation(&self) -> &[usize];
     fn types(&self) -> &[Direction];
-    fn i_star(&self) -> (usize, Direction);
+
+    fn num_queries(&self) -> usize {
+        self.permutation().len()
+    }
+    fn same(&self) -> &[usize] {
+        &self.permutation()[..self.num_queries() - self.types().len()]
+    }
+    fn different(&self) -> DifferentIter<'_> {
+        self.permutation()[self.same().len()..]
+            .iter()
+            .copied()
+            .zip(self.types().iter().copied())
+    }
+    fn i_star(&self) -> (usize, Direction) {
+        self.different().next().unwrap()
+    }
+    fn validate(&self) -> Result<(), ValidationError> {
+        validate_cs(self)
+    }
+    fn id(&self) -> String {
+        let perm = repr_slice(self.permutation());
+        let cs_type = repr_slice(self.types());
+        format!("{perm},{},{cs_type}", self.same().len())
+    }
 }
 
 use std::iter::Copied;
 use std::iter::Zip;
-type DifferentIter<'a> =
+pub type DifferentIter<'a> =
     Zip<Copied<std::slice::Iter<'a, usize>>, Copied<std::slice::Iter<'a, Direction>>>;
 
-impl<const N: usize, const DIFF: usize> CollisionStructure<N, DIFF> {
-    pub fn same(&self) -> &[usize] {
-        &self.permutation[..N - DIFF]
+impl<const N: usize, const DIFF: usize> CollisionStructureTrait for CollisionStructure<N, DIFF> {
+    fn permutation(&self) -> &[usize] {
+        &self.permutation
     }
-    pub fn different(&self) -> DifferentIter {
-        self.permutation[N - DIFF..]
-            .iter()
-            .copied()
-            .zip(self.cs_type.iter().copied())
-    }
-    pub fn directions(&self) -> &[Direction] {
+    fn types(&self) -> &[Direction] {
         &self.cs_type
     }
-    pub fn i_star(&self) -> (usize, Direction) {
-        (self.permutation[N - DIFF], self.cs_type[0])
-    }
 }
 
 fn repr_slice<'a>(row: impl IntoIterator<Item = &'a (impl std::fmt::Display + 'a)>) -> String {
     row.into_iter().map(|entry| format!("{}", entry)).collect()
 }
 
-impl<const N: usize, const DIFF: usize> CollisionStructure<N, DIFF> {
-    pub fn id(&self) -> String {
-        let perm = repr_slice(&self.permutation);
-        // let perm = format!("{}{}", self.permutation[0], self.permutation[1]);
-        let cs_type = repr_slice(&self.cs_type);
-        format!("{perm},{},{cs_type}", N - DIFF)
-    }
-}
-// impl CollisionStructure<1, 1> {
-//     pub fn id(&self) -> String {
-//         let perm = format!("{}", self.permutation[0]);
-//         format!("{perm},{},{}", 0, self.cs_type[0])
-//     }
-// }
-
-fn is_in_span<const BASE: usize>(v: RowSVector<u8, BASE>, fixed: &[RowSVector<u8, BASE>]) -> bool {
-    let matrix = na::OMatrix::<u8, Dynamic, Const<BASE>>::from_rows(fixed).cast::<f64>();
-    let rows_with_v: Vec<_> = fixed.iter().copied().chain([v].into_iter()).collect();
-    let matrix_with_v =
-        na::OMatrix::<u8, Dynamic, Const<BASE>>::from_rows(&rows_with_v).cast::<f64>();
-    if matrix.svd(false, false).rank(EPSILON) == matrix_with_v.svd(false, false).rank(EPSILON) {
-        return true;
-    }
-    false
-}
-fn full_rank<const BASE: usize>(rows: &[RowSVector<u8, BASE>]) -> bool {
-    // println!("fixed: {:?}", fixed);
-    // println!("free:  {:?}", v);
-    let matrix = na::OMatrix::<u8, Dynamic, Const<BASE>>::from_rows(rows)
-        .cast::<f64>()
-        .transpose();
-    let rank = matrix.svd(false, false).rank(EPSILON);
-    rank < min(rows.len(), BASE)
+impl<const BASE: usize, const N: usize, const OUT: usize, F: Field>
+    AlgebraicRepresentation<BASE, N, OUT, F>
+{
+    /// All rows of `m` are fixed, a collision has to agree on every output.
+    pub fn has_cs(&self, cs: &impl CollisionStructureTrait) -> bool {
+        self.has_cs_with(cs, Backend::default())
+    }
+    /// # Panics
+    /// If `cs` is a collision structure for another number of queries than `N`.
+    pub fn has_cs_with(&self, cs: &impl CollisionStructureTrait, backend: Backend) -> bool {
+        assert_eq!(
+            cs.num_queries(),
+            N,
+            "collision structure for the wrong number of queries"
+        );
+        check_cs(self, cs, backend)
+    }
+    /// Like `has_cs`, but also tells which condition fails and why.
+    pub fn explain_cs(&self, cs: &impl CollisionStructureTrait) -> CsExplanation<F> {
+        self.explain_cs_with(cs, Backend::default())
+    }
+    pub fn explain_cs_with(
+        &self,
+        cs: &impl CollisionStructureTrait,
+        backend: Backend,
+    ) -> CsExplanation<F> {
+        assert_eq!(
+            cs.num_queries(),
+            N,
+            "collision structure for the wrong number of queries"
+        );
+        explain_cs(self, cs, backend)
+    }
+    pub fn is_degenerate(&self) -> bool {
+        self.is_degenerate_with(Backend::default())
+    }
+    pub fn is_degenerate_with(&self, backend: Backend) -> bool {
+        is_degenerate(self, backend)
+    }
 }
 
-impl<const BASE: usize, const N: usize> AlgebraicRepresentation<BASE, N, 1> {
-    pub fn has_cs<const I_STAR: usize>(&self, cs: &CollisionStructure<N, I_STAR>) -> bool {
-        let same = cs
-            .same()
-            // .permutation
-            .iter()
-            // .take(cs.i_star)
-            .map(|i| &self.constraints[*i]);
-        let mut fixed: Vec<_> = same.into_iter().flat_map(|c| [c.k, c.x, c.y]).collect();
-        fixed.push(self.m);
-        // Check 2: the i^* query is unconstraint on both sides
-        let (i_star, dir_star) = cs.i_star();
-        let c_star = &self.constraints[i_star];
-        let (free_1, free_2) = match dir_star {
-            Direction::F => (c_star.k, c_star.x),
-            Direction::B => (c_star.k, c_star.y),
-        };
-        if is_in_span(free_1, &fixed) && is_in_span(free_2, &fixed) {
-            // println!("Cond 2 not fulfilled");
-            return false;
+/// Why a collision structure does not apply to a program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsFailure {
+    /// The structure has no different query, so there is no i^*.
+    NoDifferentQuery,
+    /// The query goes to a random oracle, which can not be queried backward.
+    NotAllowed { query: usize, dir: Direction },
+    /// Condition 2: the key and the input of i^* in direction `dir` are both fixed.
+    IStarDetermined { query: usize, dir: Direction },
+    /// The query repeats the query `earlier` to the same oracle, so its answer is not fresh.
+    RepeatsQuery { query: usize, earlier: usize },
+    /// Condition 3: the free side of the query in direction `dir` is in the span of the
+    /// fixed vectors.
+    FreeSideFixed { query: usize, dir: Direction },
+}
+
+/// The result of `explain_cs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsExplanation<F> {
+    pub cs_id: String,
+    /// `None` if the structure applies.
+    pub failure: Option<CsFailure>,
+    /// The vectors violating the failed condition: the key and the input of i^*,
+    /// the key and the input of a repeated query, or the free side of a query.
+    pub offending: Vec<Vec<F>>,
+    /// The fixed vectors at the moment the condition failed, in the order they were
+    /// fixed. If the structure applies, all vectors fixed at the end.
+    pub fixed: Vec<Vec<F>>,
+}
+
+impl<F: Field> fmt::Display for CsExplanation<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows = |rows: &[Vec<F>]| rows.iter().map(|row| repr_slice(row)).join(" ");
+        let offending = rows(&self.offending);
+        match self.failure {
+            None => writeln!(f, "{} applies", self.cs_id)?,
+            Some(failure) => {
+                write!(f, "{} does not apply: ", self.cs_id)?;
+                match failure {
+                    CsFailure::NoDifferentQuery => writeln!(f, "there is no different query")?,
+                    CsFailure::NotAllowed { query, dir } => {
+                        writeln!(f, "query {query} can not be made in direction {dir}")?
+                    }
+                    CsFailure::IStarDetermined { query, dir } => writeln!(
+                        f,
+                        "the key and input {offending} of i* = {query} ({dir}) are both fixed"
+                    )?,
+                    CsFailure::RepeatsQuery { query, earlier } => writeln!(
+                        f,
+                        "query {query} with key and input {offending} repeats query {earlier}"
+                    )?,
+                    CsFailure::FreeSideFixed { query, dir } => writeln!(
+                        f,
+                        "the free side {offending} of query {query} ({dir}) is fixed"
+                    )?,
+                }
+            }
         }
+        write!(f, "fixed: {}", rows(&self.fixed))
+    }
+}
 
-        // Check 3: Every query is onconstrained on one side
-        for (i, dir) in cs.different() {
-            // println!("{i}");
-            let c = &self.constraints[i];
-            let (should_be_free, fixed_1, fixed_2) = match dir {
-                Direction::F => (c.y, c.k, c.x),
-                Direction::B => (c.x, c.k, c.y),
+// Records every pushed vector, to list the fixed set in `explain_cs`.
+struct RecordingSpan<F> {
+    span: BackendSpan<F>,
+    rows: Vec<Vec<F>>,
+}
+
+impl<F: Field> Span<F> for RecordingSpan<F> {
+    fn push(&mut self, v: &[F]) {
+        self.span.push(v);
+        self.rows.push(v.to_vec());
+    }
+    fn contains(&self, v: &[F]) -> bool {
+        self.span.contains(v)
+    }
+    fn rank(&self) -> usize {
+        self.span.rank()
+    }
+}
+
+/// The structures are built by the callers, so a structure that is not valid for the
+/// program is a bug rather than a verdict. It is only looked for in debug builds, since
+/// censuses check the same structures for millions of programs.
+fn debug_assert_cs_fits<F: Field>(p: &impl Program<F>, cs: &impl CollisionStructureTrait) {
+    debug_assert!(
+        cs.validate().is_ok() && cs.num_queries() == p.num_queries(),
+        "{:?} with types {:?} is not a collision structure for {} queries",
+        cs.permutation(),
+        cs.types(),
+        p.num_queries()
+    );
+}
+
+pub(crate) fn check_cs<F: Field>(
+    p: &impl Program<F>,
+    cs: &impl CollisionStructureTrait,
+    backend: Backend,
+) -> bool {
+    debug_assert_cs_fits(p, cs);
+    let mut fixed = BackendSpan::new(p.base(), backend);
+    check_cs_in(p, cs.same(), cs.different(), &mut fixed).is_ok()
+}
+
+pub(crate) fn explain_cs<F: Field>(
+    p: &impl Program<F>,
+    cs: &impl CollisionStructureTrait,
+    backend: Backend,
+) -> CsExplanation<F> {
+    debug_assert_cs_fits(p, cs);
+    let mut fixed = RecordingSpan {
+        span: BackendSpan::new(p.base(), backend),
+        rows: vec![],
+    };
+    let failure = check_cs_in(p, cs.same(), cs.different(), &mut fixed).err();
+    let input_side = |query: usize, dir: Direction| {
+        let (k, plain, cipher) = p.query(query).cipher_rows();
+        match dir {
+            Direction::F => vec![k.to_vec(), plain.to_vec()],
+            Direction::B => vec![k.to_vec(), cipher.to_vec()],
+        }
+    };
+    let offending = match failure {
+        None | Some(CsFailure::NoDifferentQuery) | Some(CsFailure::NotAllowed { .. }) => vec![],
+        Some(CsFailure::IStarDetermined { query, dir }) => input_side(query, dir),
+        Some(CsFailure::RepeatsQuery { query, .. }) => {
+            let dir = cs.different().find(|&(i, _)| i == query).unwrap().1;
+            input_side(query, dir)
+        }
+        Some(CsFailure::FreeSideFixed { query, dir }) => {
+            let (_, plain, cipher) = p.query(query).cipher_rows();
+            let free = match dir {
+                Direction::F => cipher,
+                Direction::B => plain,
             };
-            fixed.push(fixed_1);
-            fixed.push(fixed_2);
-            if is_in_span(should_be_free, &fixed) {
-                // println!("Cond 3 not fulfilled at {i}");
-                return false;
-            }
-            fixed.push(should_be_free);
+            vec![free.to_vec()]
         }
+    };
+    CsExplanation {
+        cs_id: cs.id(),
+        failure,
+        offending,
+        fixed: fixed.rows,
+    }
+}
 
-        true
+fn check_cs_in<F: Field>(
+    p: &impl Program<F>,
+    same: &[usize],
+    mut different: impl Iterator<Item = (usize, Direction)>,
+    fixed: &mut impl Span<F>,
+) -> Result<(), CsFailure> {
+    // The directions of a collision structure refer to the cipher, not to the
+    // program, so decryption queries are looked at as the encryption they mirror.
+    for c in same.iter().map(|i| p.query(*i)) {
+        fixed.push(c.k);
+        fixed.push(c.x);
+        fixed.push(c.y);
     }
-    pub fn is_degenerate(&self) -> bool {
-        let mut vecs: Vec<_> = self
-            .constraints
+    for j in 0..p.num_outputs() {
+        fixed.push(&p.output(j));
+    }
+    // Check 2: the i^* query is unconstraint on both sides
+    let (i_star, dir_star) = different.next().ok_or(CsFailure::NoDifferentQuery)?;
+    // Random oracle queries follow the same rules as forward cipher queries,
+    // collision structures querying them backward do not apply.
+    if !p.query(i_star).allows(dir_star) {
+        return Err(CsFailure::NotAllowed {
+            query: i_star,
+            dir: dir_star,
+        });
+    }
+    let (k_star, plain_star, cipher_star) = p.query(i_star).cipher_rows();
+    let (free_1, free_2) = match dir_star {
+        Direction::F => (k_star, plain_star),
+        Direction::B => (k_star, cipher_star),
+    };
+    if fixed.contains(free_1) && fixed.contains(free_2) {
+        return Err(CsFailure::IStarDetermined {
+            query: i_star,
+            dir: dir_star,
+        });
+    }
+
+    // Check 3: Every query is onconstrained on one side
+    let mut answered: Vec<_> = same.to_vec();
+    for (i, dir) in [(i_star, dir_star)].into_iter().chain(different) {
+        if !p.query(i).allows(dir) {
+            return Err(CsFailure::NotAllowed { query: i, dir });
+        }
+        // An identical query to the same oracle was already made, so the answer is
+        // not free even if its row is independent of the fixed ones.
+        let repeated = answered
             .iter()
-            .flat_map(|c| [c.k, c.x, c.y])
-            .collect();
-        vecs.push(self.m);
-        full_rank(&vecs)
+            .find(|&&j| p.query(j).determines(&p.query(i), dir));
+        if let Some(&earlier) = repeated {
+            return Err(CsFailure::RepeatsQuery { query: i, earlier });
+        }
+        let (k, plain, cipher) = p.query(i).cipher_rows();
+        let (should_be_free, fixed_1, fixed_2) = match dir {
+            Direction::F => (cipher, k, plain),
+            Direction::B => (plain, k, cipher),
+        };
+        fixed.push(fixed_1);
+        fixed.push(fixed_2);
+        if fixed.contains(should_be_free) {
+            return Err(CsFailure::FreeSideFixed { query: i, dir });
+        }
+        fixed.push(should_be_free);
+        answered.push(i);
     }
+
+    Ok(())
+}
+
+pub(crate) fn is_degenerate<F: Field>(p: &impl Program<F>, backend: Backend) -> bool {
+    let mut span = BackendSpan::new(p.base(), backend);
+    let mut num_rows = 0;
+    for i in 0..p.num_queries() {
+        let c = p.query(i);
+        [c.k, c.x, c.y].into_iter().for_each(|row| span.push(row));
+        num_rows += 3;
+    }
+    for j in 0..p.num_outputs() {
+        span.push(&p.output(j));
+        num_rows += 1;
+    }
+    span.rank() < min(num_rows, p.base())
 }
 
 #[cfg(test)]
@@ -281,4 +728,286 @@ mod tests {
 
         assert!(!p.has_cs(&cs));
     }
+
+    #[test]
+    fn check_coefficients_outside_gf2() {
+        use super::field::Gf256;
+        use super::Direction::*;
+        use super::Operation::*;
+
+        // y = E(a, b) with output y + 2b. Over GF(2) the coefficient 2 vanishes and
+        // the output is just y, over GF(2^8) it is the doubling of b.
+        let m = [0, 2, 1];
+        let cs = [(E, [1, 0, 0], [0, 1, 0], [0, 0, 1])];
+        let p_gf2 = AlgebraicRepresentation::<3, 1, 1, Gf2>::from_coefficients(m, cs);
+        let p_gf256 = AlgebraicRepresentation::<3, 1, 1, Gf256>::from_coefficients(m, cs);
+
+        let forward = CollisionStructure::<1, 1> {
+            permutation: [0],
+            cs_type: [F],
+        };
+        let backward = CollisionStructure::<1, 1> {
+            permutation: [0],
+            cs_type: [B],
+        };
+        assert!(!p_gf2.has_cs(&forward));
+        assert!(p_gf2.has_cs(&backward));
+        assert!(!p_gf256.has_cs(&forward));
+        assert!(!p_gf256.has_cs(&backward));
+        assert!(!p_gf256.is_degenerate());
+    }
+
+    #[test]
+    fn check_double_block_length_outputs() {
+        use super::Direction::*;
+        use super::Operation::*;
+
+        // Two Davies-Meyer calls sharing the key a: y0 = E(a, b), y1 = E(a, c).
+        let queries = [
+            (E, [1, 0, 0, 0, 0], [0, 1, 0, 0, 0], [0, 0, 0, 1, 0]),
+            (E, [1, 0, 0, 0, 0], [0, 0, 1, 0, 0], [0, 0, 0, 0, 1]),
+        ];
+        let single = AlgebraicRepresentation::<5, 2, 1>::new([0, 1, 0, 1, 0], queries);
+        let double = AlgebraicRepresentation::<5, 2, 2>::with_outputs(
+            [[0, 1, 0, 1, 0], [0, 0, 1, 0, 1]],
+            queries,
+        );
+
+        // With only y0 + b as output, the second query can be changed freely.
+        let second_query_differs = |cs_type| CollisionStructure::<2, 1> {
+            permutation: [0, 1],
+            cs_type: [cs_type],
+        };
+        assert!(single.has_cs(&second_query_differs(F)));
+        assert!(single.has_cs(&second_query_differs(B)));
+        assert!(!double.has_cs(&second_query_differs(F)));
+        assert!(!double.has_cs(&second_query_differs(B)));
+        assert!(!double.is_degenerate());
+
+        assert_eq!(double.output(1).as_ref(), [0, 0, 1, 0, 1].map(Gf2::from));
+    }
+
+    #[test]
+    fn check_decryption_mirrors_encryption() {
+        use super::Direction::*;
+        use super::Operation::*;
+
+        let forward = CollisionStructure::<1, 1> {
+            permutation: [0],
+            cs_type: [F],
+        };
+        let backward = CollisionStructure::<1, 1> {
+            permutation: [0],
+            cs_type: [B],
+        };
+        let bits = |n: u8| [n >> 1 & 1, n & 1, 0];
+        let mut mirrored_cs = 0;
+        for (m, k, x) in itertools::iproduct!(0..4, 0..4, 0..4) {
+            let mut m = bits(m);
+            m[2] = 1;
+            let c = (E, bits(k), bits(x), [0, 0, 1]);
+            let p_e = AlgebraicRepresentation::<3, 1, 1>::new(m, [c]);
+            let p_d = AlgebraicRepresentation::<3, 1, 1>::new(m, [(D, c.1, c.2, c.3)]);
+            // y = D(k, x) is the query E(k, y) = x, so forward and backward swap.
+            assert_eq!(p_e.has_cs(&forward), p_d.has_cs(&backward));
+            assert_eq!(p_e.has_cs(&backward), p_d.has_cs(&forward));
+            mirrored_cs += p_d.has_cs(&forward) as usize;
+        }
+        assert_eq!(mirrored_cs, 18);
+    }
+
+    #[test]
+    fn check_decryption_in_two_query_program() {
+        use super::Direction::*;
+        use super::Operation::*;
+
+        let flip = |d| if d == F { B } else { F };
+        let e = [
+            (E, [0, 1, 0, 0, 0], [1, 0, 0, 0, 0], [0, 0, 0, 1, 0]),
+            (E, [0, 0, 1, 0, 0], [0, 1, 0, 1, 0], [0, 0, 0, 0, 1]),
+        ];
+        let mut d = e;
+        d[1].0 = D;
+        let p_e = AlgebraicRepresentation::<5, 2, 1>::new([0, 0, 1, 0, 1], e);
+        let p_d = AlgebraicRepresentation::<5, 2, 1>::new([0, 0, 1, 0, 1], d);
+
+        let mut found = vec![];
+        for permutation in [[0, 1], [1, 0]] {
+            for cs_type in itertools::iproduct!([F, B], [F, B]).map(|(a, b)| [a, b]) {
+                let cs = CollisionStructure::<2, 2> {
+                    permutation,
+                    cs_type,
+                };
+                // Only the direction of the second query is mirrored.
+                let mut mirrored = cs.clone();
+                let position = permutation.iter().position(|&i| i == 1).unwrap();
+                mirrored.cs_type[position] = flip(cs_type[position]);
+                assert_eq!(p_e.has_cs(&cs), p_d.has_cs(&mirrored));
+                if p_d.has_cs(&cs) {
+                    found.push(cs.id());
+                }
+            }
+        }
+        assert_eq!(found, ["10,0,FB"]);
+        let bb = CollisionStructure::<2, 2> {
+            permutation: [1, 0],
+            cs_type: [B, B],
+        };
+        assert!(p_e.has_cs(&bb));
+    }
+
+    #[test]
+    fn check_hashed_key() {
+        use super::Direction::*;
+        use super::Operation::*;
+
+        // y0 = H(a), y1 = E(y0, b), with and without the feed forward of b.
+        let queries = [
+            (H, [0, 0, 0, 0], [1, 0, 0, 0], [0, 0, 1, 0]),
+            (E, [0, 0, 1, 0], [0, 1, 0, 0], [0, 0, 0, 1]),
+        ];
+        let davies_meyer = AlgebraicRepresentation::<4, 2, 1>::new([0, 1, 0, 1], queries);
+        let no_feed_forward = AlgebraicRepresentation::<4, 2, 1>::new([0, 0, 0, 1], queries);
+
+        let mut css = vec![];
+        for permutation in [[0, 1], [1, 0]] {
+            for cs_type in itertools::iproduct!([F, B], [F, B]).map(|(a, b)| [a, b]) {
+                let cs = CollisionStructure::<2, 2> {
+                    permutation,
+                    cs_type,
+                };
+                assert!(!davies_meyer.has_cs(&cs));
+                if no_feed_forward.has_cs(&cs) {
+                    css.push(cs.id());
+                }
+            }
+        }
+        // Inverting the cipher gives a collision, inverting the hash does not.
+        assert_eq!(css, ["01,0,FB"]);
+        let hash_backward = CollisionStructure::<2, 1> {
+            permutation: [1, 0],
+            cs_type: [B],
+        };
+        assert!(!no_feed_forward.has_cs(&hash_backward));
+    }
+
+    #[test]
+    fn check_random_oracle_only_forward() {
+        use super::Direction::*;
+        use super::Operation::*;
+
+        // With output y, inverting E(a, b) = y gives a collision. For H(a, b) = y it does not.
+        let cipher = AlgebraicRepresentation::<3, 1, 1>::new(
+            [0, 0, 1],
+            [(E, [1, 0, 0], [0, 1, 0], [0, 0, 1])],
+        );
+        let hash = AlgebraicRepresentation::<3, 1, 1>::new(
+            [0, 0, 1],
+            [(H, [1, 0, 0], [0, 1, 0], [0, 0, 1])],
+        );
+        let backward = CollisionStructure::<1, 1> {
+            permutation: [0],
+            cs_type: [B],
+        };
+        let forward = CollisionStructure::<1, 1> {
+            permutation: [0],
+            cs_type: [F],
+        };
+        assert!(cipher.has_cs(&backward));
+        assert!(!hash.has_cs(&backward));
+        assert!(!hash.has_cs(&forward));
+    }
+
+    #[test]
+    fn check_identical_queries_to_independent_ciphers() {
+        use super::Direction::*;
+        use super::Operation::*;
+
+        // y0 = E(a, b) and y1 = E(a, b), output y0 + y1 + c.
+        let queries = [
+            (E, [1, 0, 0, 0, 0], [0, 1, 0, 0, 0], [0, 0, 0, 1, 0]),
+            (E, [1, 0, 0, 0, 0], [0, 1, 0, 0, 0], [0, 0, 0, 0, 1]),
+        ];
+        let one_cipher = AlgebraicRepresentation::<5, 2, 1>::new([0, 0, 1, 1, 1], queries);
+        let two_ciphers =
+            AlgebraicRepresentation::<5, 2, 1>::new([0, 0, 1, 1, 1], queries).with_oracles([0, 1]);
+        assert_eq!(two_ciphers.constraints[1].oracle, 1);
+
+        let ff = CollisionStructure::<2, 2> {
+            permutation: [0, 1],
+            cs_type: [F, F],
+        };
+        // With a single cipher the second query repeats the first one, so y1 is never
+        // a fresh answer. With two ciphers it is.
+        assert!(!one_cipher.has_cs(&ff));
+        assert!(two_ciphers.has_cs(&ff));
+        // The rule does not depend on how ranks are computed.
+        assert!(!one_cipher.has_cs_with(&ff, Backend::Real));
+        // Backward the second query fixes y1 instead of b, so it never repeats the first.
+        let fb = CollisionStructure::<2, 2> {
+            permutation: [0, 1],
+            cs_type: [F, B],
+        };
+        assert_eq!(one_cipher.has_cs(&fb), two_ciphers.has_cs(&fb));
+    }
+
+    #[test]
+    fn check_explain_cs() {
+        use super::Direction::*;
+        use super::Operation::*;
+        use crate::dynamic::DynCollisionStructure;
+
+        // y0 = E(a, b), y1 = E(c, y0), output y1
+        let p = AlgebraicRepresentation::<5, 2, 1>::new(
+            [0, 0, 0, 0, 1],
+            [
+                (E, [1, 0, 0, 0, 0], [0, 1, 0, 0, 0], [0, 0, 0, 1, 0]),
+                (E, [0, 0, 1, 0, 0], [0, 0, 0, 1, 0], [0, 0, 0, 0, 1]),
+            ],
+        );
+        let row = |r: [u8; 5]| r.map(Gf2::from).to_vec();
+        let cs: DynCollisionStructure = "10,0,BB".parse().unwrap();
+        let explanation = p.explain_cs(&cs);
+        assert_eq!(explanation.failure, None);
+        assert_eq!(explanation.fixed.len(), 7);
+
+        // Forward, y1 is determined by the output.
+        let cs: DynCollisionStructure = "10,0,FF".parse().unwrap();
+        let explanation = p.explain_cs(&cs);
+        assert_eq!(
+            explanation.failure,
+            Some(CsFailure::FreeSideFixed { query: 1, dir: F })
+        );
+        assert_eq!(explanation.offending, [row([0, 0, 0, 0, 1])]);
+        assert_eq!(
+            explanation.fixed,
+            [
+                row([0, 0, 0, 0, 1]),
+                row([0, 0, 1, 0, 0]),
+                row([0, 0, 0, 1, 0])
+            ]
+        );
+        assert_eq!(
+            explanation.to_string(),
+            "10,0,FF does not apply: the free side 00001 of query 1 (F) is fixed\n\
+             fixed: 00001 00100 00010"
+        );
+
+        // If the inputs are outputs as well, the key and plaintext of i* are fixed.
+        let p = AlgebraicRepresentation::<3, 1, 2>::with_outputs(
+            [[1, 0, 0], [0, 1, 0]],
+            [(E, [1, 0, 0], [0, 1, 0], [0, 0, 1])],
+        );
+        let cs: DynCollisionStructure = "0,0,F".parse().unwrap();
+        let explanation = p.explain_cs(&cs);
+        assert_eq!(
+            explanation.failure,
+            Some(CsFailure::IStarDetermined { query: 0, dir: F })
+        );
+        assert_eq!(
+            explanation.offending,
+            [[1, 0, 0], [0, 1, 0]].map(|r| r.map(Gf2::from).to_vec())
+        );
+        assert_eq!(p.has_cs(&cs), explanation.failure.is_none());
+    }
 }
@@ -0,0 +1,396 @@
+use na::*;
+use nalgebra as na;
+
+use std::cell::Cell;
+
+use crate::field::{Field, Gf2};
+
+const EPSILON: f64 = 0.0001;
+
+/// Selects how ranks and spans are computed.
+///
+/// `Exact` does Gaussian elimination over the field the program is defined over.
+/// `Real` is the original implementation, which casts the entries to `f64` and
+/// compares SVD ranks. It is kept around to compare ranks with older runs and
+/// is only meaningful for programs over `Gf2`. The backend only decides how ranks
+/// are computed, the rules of the analysis are the same for both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    #[default]
+    Exact,
+    Real,
+}
+
+/// A subspace of `F^base` that only ever grows, as the set of fixed vectors in `has_cs`.
+pub trait Span<F: Field> {
+    fn push(&mut self, v: &[F]);
+    fn contains(&self, v: &[F]) -> bool;
+    fn rank(&self) -> usize;
+}
+
+/// A span over GF(2) with every vector packed into a `u64`, so `base` has to be at most 64.
+///
+/// The basis is kept in echelon form sorted by decreasing leading bit, so reducing
+/// a vector against it takes one pass of at most `base` word operations.
+#[derive(Debug, Clone, Default)]
+pub struct BitSpan {
+    basis: Vec<u64>,
+}
+
+impl BitSpan {
+    pub fn new(base: usize) -> Self {
+        assert!(
+            base <= 64,
+            "BitSpan supports at most 64 coordinates, got {base}"
+        );
+        BitSpan {
+            basis: Vec::with_capacity(base),
+        }
+    }
+
+    fn pack<F: Field>(v: &[F]) -> u64 {
+        v.iter().fold(0, |acc, e| (acc << 1) | (e.to_u64() & 1))
+    }
+
+    fn reduce(&self, mut v: u64) -> u64 {
+        for &b in &self.basis {
+            // b has no bits above its leading bit, so this never sets a higher bit of v.
+            v = v.min(v ^ b);
+        }
+        v
+    }
+}
+
+impl<F: Field> Span<F> for BitSpan {
+    fn push(&mut self, v: &[F]) {
+        debug_assert_eq!(F::ORDER, 2);
+        let v = self.reduce(Self::pack(v));
+        if v != 0 {
+            let position = self.basis.partition_point(|&b| b > v);
+            self.basis.insert(position, v);
+        }
+    }
+    fn contains(&self, v: &[F]) -> bool {
+        self.reduce(Self::pack(v)) == 0
+    }
+    fn rank(&self) -> usize {
+        self.basis.len()
+    }
+}
+
+/// A span over any field, kept in reduced row echelon form.
+#[derive(Debug, Clone, Default)]
+pub struct EchelonSpan<F> {
+    // Every row has a one at its pivot and zeros at the pivots of all other rows.
+    basis: Vec<(usize, Vec<F>)>,
+}
+
+impl<F: Field> EchelonSpan<F> {
+    pub fn new(_base: usize) -> Self {
+        EchelonSpan { basis: vec![] }
+    }
+
+    fn reduce(&self, v: &[F]) -> Vec<F> {
+        let mut v = v.to_vec();
+        for (pivot, row) in &self.basis {
+            let factor = v[*pivot];
+            if !factor.is_zero() {
+                v.iter_mut()
+                    .zip(row)
+                    .for_each(|(a, &b)| *a = *a - factor * b);
+            }
+        }
+        v
+    }
+}
+
+impl<F: Field> Span<F> for EchelonSpan<F> {
+    fn push(&mut self, v: &[F]) {
+        let mut v = self.reduce(v);
+        let pivot = match v.iter().position(|e| !e.is_zero()) {
+            Some(pivot) => pivot,
+            None => return,
+        };
+        let inv = v[pivot].inverse().unwrap();
+        v.iter_mut().for_each(|e| *e = *e * inv);
+        for (_, row) in self.basis.iter_mut() {
+            let factor = row[pivot];
+            if !factor.is_zero() {
+                row.iter_mut()
+                    .zip(&v)
+                    .for_each(|(a, &b)| *a = *a - factor * b);
+            }
+        }
+        self.basis.push((pivot, v));
+    }
+    fn contains(&self, v: &[F]) -> bool {
+        self.reduce(v).iter().all(|e| e.is_zero())
+    }
+    fn rank(&self) -> usize {
+        self.basis.len()
+    }
+}
+
+/// A system of linear equations `row · v = value`, kept as the span of the rows with
+/// their values appended. Used to pin rows of a program to concrete values and to
+/// solve for a base vector that satisfies all of them.
+#[derive(Debug, Clone, Default)]
+pub struct LinearSystem<F> {
+    // Never contains a row that is zero except for its value, so no pivot is a value.
+    equations: EchelonSpan<F>,
+}
+
+impl<F: Field> LinearSystem<F> {
+    pub fn new() -> Self {
+        LinearSystem {
+            equations: EchelonSpan { basis: vec![] },
+        }
+    }
+
+    // Whether the equations determine `row · v`, and if so, `value` minus what they
+    // determine it to be.
+    fn difference(&self, row: &[F], value: F) -> Option<F> {
+        let equation: Vec<F> = row.iter().copied().chain([value]).collect();
+        let rest = self.equations.reduce(&equation);
+        let (difference, rest) = rest.split_last().unwrap();
+        rest.iter().all(|e| e.is_zero()).then_some(*difference)
+    }
+
+    /// The value of `row · v` for every solution `v`, if the equations determine it.
+    pub fn value_of(&self, row: &[F]) -> Option<F> {
+        self.difference(row, F::ZERO)
+            .map(|difference| F::ZERO - difference)
+    }
+
+    /// Adds the equation `row · v = value`. Returns false, and leaves the system
+    /// unchanged, if it contradicts the equations so far.
+    pub fn pin(&mut self, row: &[F], value: F) -> bool {
+        if let Some(difference) = self.difference(row, value) {
+            return difference.is_zero();
+        }
+        let equation: Vec<F> = row.iter().copied().chain([value]).collect();
+        self.equations.push(&equation);
+        true
+    }
+
+    /// A solution with every coordinate that is not a pivot set to zero.
+    pub fn solution(&self, base: usize) -> Vec<F> {
+        let mut v = vec![F::ZERO; base];
+        for (pivot, equation) in &self.equations.basis {
+            v[*pivot] = equation[base];
+        }
+        v
+    }
+
+    pub fn rank(&self) -> usize {
+        self.equations.rank()
+    }
+}
+
+/// The original floating point implementation, comparing SVD ranks.
+#[derive(Debug, Clone)]
+pub struct RealSpan {
+    base: usize,
+    rows: Vec<f64>,
+    // The rank of `rows`, computed lazily since pushes often come in batches.
+    rank: Cell<Option<usize>>,
+}
+
+impl RealSpan {
+    pub fn new(base: usize) -> Self {
+        RealSpan {
+            base,
+            rows: vec![],
+            rank: Cell::new(Some(0)),
+        }
+    }
+
+    fn rank_of(&self, rows: &[f64]) -> usize {
+        let matrix = DMatrix::from_row_slice(rows.len() / self.base, self.base, rows);
+        matrix.svd(false, false).rank(EPSILON)
+    }
+}
+
+impl<F: Field> Span<F> for RealSpan {
+    fn push(&mut self, v: &[F]) {
+        self.rows.extend(v.iter().map(|e| e.to_u64() as f64));
+        self.rank.set(None);
+    }
+    fn contains(&self, v: &[F]) -> bool {
+        let mut rows_with_v = self.rows.clone();
+        rows_with_v.extend(v.iter().map(|e| e.to_u64() as f64));
+        Span::<F>::rank(self) == self.rank_of(&rows_with_v)
+    }
+    fn rank(&self) -> usize {
+        let rank = self.rank.get().unwrap_or_else(|| self.rank_of(&self.rows));
+        self.rank.set(Some(rank));
+        rank
+    }
+}
+
+/// The span implementation selected by a `Backend`. For the exact backend over
+/// GF(2) the packed `BitSpan` is used whenever the vectors fit into a word.
+#[derive(Debug, Clone)]
+pub enum BackendSpan<F> {
+    Bits(BitSpan),
+    Echelon(EchelonSpan<F>),
+    Real(RealSpan),
+}
+
+impl<F: Field> BackendSpan<F> {
+    pub fn new(base: usize, backend: Backend) -> Self {
+        match backend {
+            Backend::Exact if F::ORDER == 2 && base <= 64 => BackendSpan::Bits(BitSpan::new(base)),
+            Backend::Exact => BackendSpan::Echelon(EchelonSpan::new(base)),
+            Backend::Real => BackendSpan::Real(RealSpan::new(base)),
+        }
+    }
+}
+
+impl<F: Field> Span<F> for BackendSpan<F> {
+    fn push(&mut self, v: &[F]) {
+        match self {
+            BackendSpan::Bits(span) => span.push(v),
+            BackendSpan::Echelon(span) => span.push(v),
+            BackendSpan::Real(span) => span.push(v),
+        }
+    }
+    fn contains(&self, v: &[F]) -> bool {
+        match self {
+            BackendSpan::Bits(span) => span.contains(v),
+            BackendSpan::Echelon(span) => span.contains(v),
+            BackendSpan::Real(span) => span.contains(v),
+        }
+    }
+    fn rank(&self) -> usize {
+        match self {
+            BackendSpan::Bits(span) => Span::<F>::rank(span),
+            BackendSpan::Echelon(span) => span.rank(),
+            BackendSpan::Real(span) => Span::<F>::rank(span),
+        }
+    }
+}
+
+pub fn is_in_span<F: Field, const BASE: usize>(
+    v: RowSVector<F, BASE>,
+    fixed: &[RowSVector<F, BASE>],
+    backend: Backend,
+) -> bool {
+    let mut span = BackendSpan::new(BASE, backend);
+    fixed.iter().for_each(|row| span.push(row.as_slice()));
+    span.contains(v.as_slice())
+}
+
+pub fn rank<F: Field, const BASE: usize>(rows: &[RowSVector<F, BASE>], backend: Backend) -> usize {
+    let mut span = BackendSpan::new(BASE, backend);
+    rows.iter().for_each(|row| span.push(row.as_slice()));
+    span.rank()
+}
+
+/// Converts rows of 0/1 entries to rows over GF(2), mostly useful in tests.
+pub fn gf2_row<const BASE: usize>(row: [u8; BASE]) -> RowSVector<Gf2, BASE> {
+    RowSVector::from_row_slice(&row.map(Gf2::from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::Gf3;
+
+    #[test]
+    fn gf2_rank_differs_from_real_rank() {
+        let rows = [gf2_row([1, 1, 0]), gf2_row([0, 1, 1]), gf2_row([1, 0, 1])];
+        assert_eq!(rank(&rows, Backend::Exact), 2);
+        assert_eq!(rank(&rows, Backend::Real), 3);
+
+        let v = gf2_row([1, 0, 1]);
+        assert!(is_in_span(v, &rows[..2], Backend::Exact));
+        assert!(!is_in_span(v, &rows[..2], Backend::Real));
+    }
+
+    #[test]
+    fn gf2_rank_of_empty_and_zero_rows() {
+        let rows: [RowSVector<Gf2, 4>; 0] = [];
+        assert_eq!(rank(&rows, Backend::Exact), 0);
+        let rows = [gf2_row([0, 0, 0, 0]), gf2_row([0, 0, 0, 0])];
+        assert_eq!(rank(&rows, Backend::Exact), 0);
+        assert!(is_in_span(gf2_row([0, 0, 0, 0]), &[], Backend::Exact));
+        assert!(!is_in_span(gf2_row([0, 0, 1, 0]), &[], Backend::Exact));
+    }
+
+    #[test]
+    fn exact_rank_over_gf3() {
+        // Over GF(3) the row (2,1) is twice (1,2), over GF(2) the rows are (0,1) and (1,0).
+        let rows = [
+            RowVector2::new(Gf3::from_u64(1), Gf3::from_u64(2)),
+            RowVector2::new(Gf3::from_u64(2), Gf3::from_u64(1)),
+        ];
+        assert_eq!(rank(&rows, Backend::Exact), 1);
+        let rows = [gf2_row([1, 2]), gf2_row([2, 1])];
+        assert_eq!(rank(&rows, Backend::Exact), 2);
+    }
+
+    fn check_span_against_rank<S: Span<Gf3>>(mut span: S) {
+        let rows = [[1, 2, 0, 1], [2, 1, 0, 2], [0, 1, 1, 0], [1, 0, 1, 1]];
+        let rows: Vec<_> = rows.iter().map(|r| r.map(Gf3::from_u64)).collect();
+        for (i, row) in rows.iter().enumerate() {
+            let fixed: Vec<_> = rows[..i]
+                .iter()
+                .map(|r| RowSVector::<Gf3, 4>::from_row_slice(r))
+                .collect();
+            let v = RowSVector::<Gf3, 4>::from_row_slice(row);
+            assert_eq!(span.contains(row), is_in_span(v, &fixed, Backend::Exact));
+            span.push(row);
+        }
+        // The second row is twice the first and the last is the sum of the first and third.
+        assert_eq!(span.rank(), 2);
+    }
+
+    #[test]
+    fn echelon_span_over_gf3() {
+        check_span_against_rank(EchelonSpan::new(4));
+    }
+
+    #[test]
+    fn bit_span_matches_echelon_span() {
+        let rows = [
+            [1, 0, 1, 1, 0],
+            [0, 1, 1, 0, 0],
+            [1, 1, 0, 1, 0],
+            [0, 0, 0, 0, 1],
+            [1, 0, 0, 1, 1],
+            [0, 0, 1, 0, 0],
+        ];
+        let mut bits = BitSpan::new(5);
+        let mut echelon = EchelonSpan::new(5);
+        for row in rows.map(|r| r.map(Gf2::from)) {
+            assert_eq!(bits.contains(&row), echelon.contains(&row));
+            bits.push(&row);
+            echelon.push(&row);
+            assert_eq!(Span::<Gf2>::rank(&bits), echelon.rank());
+        }
+        assert_eq!(echelon.rank(), 4);
+    }
+
+    #[test]
+    fn linear_system_over_gf3() {
+        let row = |r: [u64; 3]| r.map(Gf3::from_u64);
+        let mut system = LinearSystem::new();
+        assert!(system.pin(&row([1, 1, 0]), Gf3::from_u64(2)));
+        assert!(system.pin(&row([0, 1, 2]), Gf3::from_u64(1)));
+        assert_eq!(system.value_of(&row([0, 0, 1])), None);
+        // (1, 2, 2) is the sum of both rows, so its value is 2 + 1.
+        assert_eq!(system.value_of(&row([1, 2, 2])), Some(Gf3::from_u64(0)));
+        assert!(!system.pin(&row([1, 2, 2]), Gf3::from_u64(1)));
+        assert_eq!(system.rank(), 2);
+
+        let v = system.solution(3);
+        let dot = |r: [Gf3; 3]| {
+            r.iter()
+                .zip(&v)
+                .fold(Gf3::ZERO, |acc, (&a, &b)| acc + a * b)
+        };
+        assert_eq!(dot(row([1, 1, 0])), Gf3::from_u64(2));
+        assert_eq!(dot(row([0, 1, 2])), Gf3::from_u64(1));
+    }
+}
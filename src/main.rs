@@ -1,98 +1,73 @@
 use itertools::iproduct;
 use itertools::Itertools;
 use linicrypt::print_grid::print_grid;
-use na::*;
-use nalgebra as na;
-use std::collections::HashMap;
-
-use linicrypt::{AlgebraicRepresentation, CollisionStructure, Constraint, Direction, Operation};
-
-fn generate_all_cs_2<const DIFF: usize>() -> impl Iterator<Item = CollisionStructure<2, DIFF>> {
-    use Direction::*;
-    let perms = (0..2).permutations(2);
-    let types = (0..DIFF).map(|_| vec![F, B]).multi_cartesian_product();
-    iproduct!(perms, types).map(|(p, t)| CollisionStructure::<2, DIFF> {
-        permutation: p.try_into().unwrap(),
-        cs_type: t.try_into().unwrap(),
-    })
-}
-
-fn generate_all_cs_1<const DIFF: usize>() -> impl Iterator<Item = CollisionStructure<1, DIFF>> {
-    use Direction::*;
-    let perms = (0..1).permutations(1);
-    let types = (0..DIFF).map(|_| vec![F, B]).multi_cartesian_product();
-    iproduct!(perms, types).map(|(p, t)| CollisionStructure::<1, DIFF> {
-        permutation: p.try_into().unwrap(),
-        cs_type: t.try_into().unwrap(),
-    })
-}
-
-fn generate_all_vecs<const BASE: usize, const DIM: usize>(
-    last_entries: [u8; DIM],
-) -> impl Iterator<Item = RowSVector<u8, BASE>> {
-    (0..(BASE - DIM))
-        .map(|_| 0..=1)
-        .multi_cartesian_product()
-        .map(move |v| RowSVector::<u8, BASE>::from_iterator(v.into_iter().chain(last_entries)))
-}
-
-fn generate_all_constraints<const BASE: usize, const ZEROS: usize>(
-) -> impl Iterator<Item = Constraint<BASE>> {
-    let ks1: Vec<_> = generate_all_vecs::<BASE, ZEROS>([0; ZEROS]).collect();
-    let xs1: Vec<_> = generate_all_vecs::<BASE, ZEROS>([0; ZEROS]).collect();
-    let mut y1 = RowSVector::<u8, BASE>::zeros();
-    y1[BASE - ZEROS] = 1;
-    iproduct!(ks1, xs1).map(move |(k, x)| Constraint {
-        op: Operation::E,
-        k,
-        x,
-        y: y1,
-    })
-}
+use linicrypt::space::ProgramSpace;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 
-fn generate_i_2_1_programs<const BASE: usize>() -> Vec<AlgebraicRepresentation<BASE, 2, 1>> {
-    let ms = generate_all_vecs::<BASE, 1>([1]);
-    let c1s: Vec<_> = generate_all_constraints::<BASE, 2>().collect();
-    let c2s: Vec<_> = generate_all_constraints::<BASE, 1>().collect();
+use linicrypt::attack::{birthday_attack, AttackPlan};
+use linicrypt::canonical::{equivalence_classes, symmetry_classes};
+use linicrypt::census::{check_in_order, combination, Census};
+use linicrypt::checkpoint::{merge_shards, resume_census, resume_search, Checkpoint};
+use linicrypt::cipher::{LazyCipher, SplitMix64};
+use linicrypt::degeneracy::{degeneracies, has_degeneracy, DegeneracyKind};
+use linicrypt::dsl::{parse, to_text};
+use linicrypt::dynamic::{
+    all_collision_structures, collision_structures, DynAlgebraicRepresentation,
+    DynCollisionStructure,
+};
+use linicrypt::field::{Field, Gf2, Gf4096};
+use linicrypt::linalg::Backend;
+use linicrypt::{AlgebraicRepresentation, CollisionStructureTrait, Operation, Program};
 
-    iproduct!(ms, c1s, c2s)
-        .map(|(m, c1, c2)| AlgebraicRepresentation {
-            m,
-            constraints: [c1, c2],
-        })
-        .collect()
-}
-
-fn generate_2_1_1_programs<const BASE: usize>() -> Vec<AlgebraicRepresentation<BASE, 1, 1>> {
-    let ms = generate_all_vecs::<BASE, 1>([1]);
-    let css: Vec<_> = generate_all_constraints::<BASE, 1>().collect();
-
-    iproduct!(ms, css)
-        .map(|(m, cs)| AlgebraicRepresentation {
-            m,
-            constraints: [cs],
-        })
-        .collect()
+/// All programs with `N` queries and one output that uses the answer of the last query,
+/// where every query chooses from `queries`. The programs are built one at a time.
+fn generate_programs<F: Field, const BASE: usize, const N: usize>(
+    queries: &[(Operation, usize)],
+) -> impl Iterator<Item = AlgebraicRepresentation<BASE, N, 1, F>> {
+    ProgramSpace::<F>::new(BASE - N, N, 1)
+        .with_operations(queries)
+        .with_last_answer_in_output()
+        .iter()
+        .map(|p| AlgebraicRepresentation::try_from(p).unwrap())
 }
 
-fn repr_vector<const BASE: usize>(row: RowSVector<u8, BASE>) -> String {
+fn repr_vector<F: Field>(row: &[F]) -> String {
     row.iter().map(|entry| format!("{}", entry)).collect()
 }
 
-fn linicrypt_to_lines<const BASE: usize, const N: usize>(
-    p: &AlgebraicRepresentation<BASE, N, 1>,
-) -> Vec<String> {
-    let m_line = format!(" M={}", repr_vector(p.m));
-    let mut lines = vec![m_line];
-    for i in 0..(N) {
-        lines.push(format!("{i}k={}", repr_vector(p.constraints[i].k)));
-        lines.push(format!("{i}x={}", repr_vector(p.constraints[i].x)));
-        lines.push(format!("{i}y={}", repr_vector(p.constraints[i].y)));
+fn linicrypt_to_lines<F: Field>(p: &impl Program<F>) -> Vec<String> {
+    let mut lines = vec![];
+    for j in 0..p.num_outputs() {
+        // Single outputs are not numbered, to keep the grids of the README unchanged.
+        let label = if p.num_outputs() == 1 {
+            " ".into()
+        } else {
+            j.to_string()
+        };
+        lines.push(format!("{label}M={}", repr_vector(&p.output(j))));
+    }
+    for i in 0..p.num_queries() {
+        let q = p.query(i);
+        lines.push(format!("{i}k={}", repr_vector(q.k)));
+        lines.push(format!("{i}x={}", repr_vector(q.x)));
+        // Decryption and hash queries are marked, the input is still x and the output y.
+        // Queries to other oracles than the first are marked with the oracle index.
+        let op = match (q.op, q.oracle) {
+            (Operation::E, 0) => "".into(),
+            (op, 0) => format!(" {op:?}"),
+            (op, oracle) => format!(" {op:?}{oracle}"),
+        };
+        lines.push(format!("{i}y={}{op}", repr_vector(q.y)));
     }
     lines
 }
 
-pub fn print_linicrypt<const BASE: usize, const N: usize>(p: &AlgebraicRepresentation<BASE, N, 1>) {
+pub fn print_linicrypt<F: Field>(p: &impl Program<F>) {
     let lines = linicrypt_to_lines(p);
     for line in lines {
         println!("{line}");
@@ -100,7 +75,7 @@ pub fn print_linicrypt<const BASE: usize, const N: usize>(p: &AlgebraicRepresent
 }
 
 pub fn linicrypt_to_lines_infos(p: &AlgebraicRepresentation<5, 2, 1>) -> Vec<String> {
-    let all_cs = generate_all_cs_2::<2>();
+    let all_cs = collision_structures(2, 2);
     let mut cs_infos = all_cs
         .map(|cs| {
             if p.has_cs(&cs) {
@@ -116,162 +91,796 @@ pub fn linicrypt_to_lines_infos(p: &AlgebraicRepresentation<5, 2, 1>) -> Vec<Str
     lines
 }
 
-// TODO clean this up, need some struct to hold cs analysis data and print cs_id's
-fn check_css<const BASE: usize, const N: usize, const DIFF: usize>(
-    p: &AlgebraicRepresentation<BASE, N, 1>,
-    css: &[CollisionStructure<N, DIFF>],
-    counter: &mut HashMap<String, usize>,
-) -> (Vec<usize>, Vec<String>) {
-    css.iter()
-        .map(|cs| {
-            let cs_id = cs.id();
-            if p.has_cs(cs) {
-                let out = (1, format!("Y{cs_id}"));
-                *counter.entry(cs_id).or_insert(0) += 1;
-                out
+// The lines printed below a program for the structures of a combination, see
+// `census::combination`. The structures that apply are marked with a Y.
+fn cs_info(census: &Census, combination: &[usize]) -> Vec<String> {
+    census
+        .ids
+        .iter()
+        .zip(combination)
+        .map(|(id, &applies)| {
+            if applies == 1 {
+                format!("Y{id}")
             } else {
-                (0, format!(" {cs_id}"))
+                format!(" {id}")
             }
         })
-        .unzip()
+        .collect()
 }
 
-fn compression_functions() {
-    println!();
-    println!("Analyzing all 64 compression schemes with 2 input, 1 queries and 1 output.");
-    let ps = generate_2_1_1_programs::<{ 2 + 1 }>();
-    let css: Vec<_> = generate_all_cs_1::<1>().collect();
+fn print_cs_counts(census: &Census) {
+    for (id, count) in census.ids.iter().zip(&census.per_cs) {
+        println!("{id}: {count}");
+    }
+}
 
-    let mut counter: HashMap<String, usize> = HashMap::new();
-    let mut cells = vec![];
+fn compression_functions(backend: Backend, queries: &[(Operation, usize)]) {
+    let ps = generate_programs::<Gf2, { 2 + 1 }, 1>(queries);
+    let css: Vec<_> = collision_structures(1, 1).collect();
 
-    for p in &ps {
-        let (mut _cs, mut info) = check_css(p, &css, &mut counter);
-        let mut cell = linicrypt_to_lines(p);
-        cell.append(&mut info);
-        // print_linicrypt(p);
-        cells.push(cell);
-    }
+    let mut census = Census::new(&css);
+    let mut cells = vec![];
+    check_in_order(
+        ps,
+        |p| combination(p, &css, backend),
+        |p, combination| {
+            census.record(&combination, 1);
+            let mut cell = linicrypt_to_lines(&p);
+            cell.append(&mut cs_info(&census, &combination));
+            cells.push(cell);
+        },
+    );
 
+    println!();
+    println!(
+        "Analyzing all {} compression schemes with 2 input, 1 queries and 1 output.",
+        census.programs
+    );
     print_grid(cells, 4);
-    println!("{:?}", counter);
+    print_cs_counts(&census);
 }
 
 fn repr_slice<'a>(row: impl IntoIterator<Item = &'a (impl std::fmt::Display + 'a)>) -> String {
     row.into_iter().map(|entry| format!("{}", entry)).collect()
 }
 
-fn print_comb_counter(
-    css1: &[CollisionStructure<2, 2>],
-    css2: &[CollisionStructure<2, 1>],
-    counter: HashMap<Vec<usize>, usize>,
-) {
+fn print_comb_counter(census: &Census) {
     println!("These combinations of types occured.");
     println!("This is the order of types used in the binary representation of the combination.");
-    for cs in css1 {
-        println!("{}", cs.id());
-    }
-    for cs in css2 {
-        println!("{}", cs.id());
-    }
-    let mut counter: Vec<(Vec<usize>, usize)> = counter.into_iter().collect();
-    counter.sort_by_cached_key(|(comb, _count)| comb.iter().fold(0, |acc, &b| acc * 2 + b as u32));
-    for (comb, count) in counter.iter() {
-        if *count != 0 {
-            let comb_str = repr_slice(comb);
-            println!("{}: {count}", comb_str);
-        }
+    for id in &census.ids {
+        println!("{id}");
+    }
+    for (comb, count) in &census.combinations {
+        println!("{}: {count}", repr_slice(comb));
     }
 }
 
-fn collision_structure_examples() {
+// The programs with 3 inputs, 2 queries and 1 output that `collision_structure_examples`
+// counts, the structures it counts, and the description of its checkpoints.
+fn examples_run(
+    backend: Backend,
+    queries: &[(Operation, usize)],
+    degenerate: &[DegeneracyKind],
+) -> (ProgramSpace<Gf2>, Vec<DynCollisionStructure>, String) {
+    let space = space_from_arg("3", queries);
+    let css: Vec<_> = collision_structures(2, 2)
+        .chain(collision_structures(2, 1))
+        .collect();
+    let description = format!("{}, examples", run_description(&space, backend, degenerate));
+    (space, css, description)
+}
+
+// Identifies the checkpoints of a census of `space`.
+fn run_description(
+    space: &ProgramSpace<Gf2>,
+    backend: Backend,
+    degenerate: &[DegeneracyKind],
+) -> String {
+    let kinds = degenerate.iter().join(" or ");
+    format!("{space}, {backend:?} ranks, degenerate if {kinds}")
+}
+
+// The non-degenerate programs with at most 2 collision structures are printed as examples.
+fn is_example(combination: &[usize]) -> bool {
+    combination.iter().sum::<usize>() <= 2
+}
+
+fn collision_structure_examples(
+    backend: Backend,
+    queries: &[(Operation, usize)],
+    degenerate: &[DegeneracyKind],
+) {
+    let (space, css, description) = examples_run(backend, queries, degenerate);
+    let start = Checkpoint::new(description, 0..space.num_programs(), &css);
+    let result = resume_census(&space, &css, backend, degenerate, start, is_example, |_| {});
+    print_examples(&space, &result);
+}
+
+// Prints the examples and the counts of a complete run of `collision_structure_examples`.
+// Only the indices of the examples are kept, the programs are built again to print them.
+fn print_examples(space: &ProgramSpace<Gf2>, result: &Checkpoint) {
     println!();
     println!("Finding interesting examples with 3 input, 2 queries and 1 output.");
-    let programs = generate_i_2_1_programs::<{ 3 + 2 }>();
+    let cells = result
+        .selected
+        .iter()
+        .map(|(&index, combination)| {
+            let mut cell = linicrypt_to_lines(&space.unrank(index).unwrap());
+            cell.append(&mut cs_info(&result.census, combination));
+            cell
+        })
+        .collect();
+    print_grid(cells, 8);
+    print_cs_counts(&result.census);
+    print_comb_counter(&result.census);
+}
 
-    let css2: Vec<_> = generate_all_cs_2::<2>().collect();
-    let css1: Vec<_> = generate_all_cs_2::<1>().collect();
+// Pass `--checkpoint <file>` to save the position of the last batch checked to the
+// file, and `--resume` to continue the search from there.
+fn secure_4_2_1(
+    backend: Backend,
+    queries: &[(Operation, usize)],
+    degenerate: &[DegeneracyKind],
+    checkpoint: Option<PathBuf>,
+) {
+    println!();
+    println!(
+        "Finding a program with 4 inputs, making 2 queries to E without a collision structure"
+    );
+    let space = space_from_arg("4", queries);
+    let css: Vec<_> = all_collision_structures(2).collect();
+    let description = format!("{}, secure", run_description(&space, backend, degenerate));
+    let start = Checkpoint::new(description, 0..space.num_programs(), &css);
+    // The batches are checked on all cores, the first program of the first batch with
+    // such a program is the first one overall.
+    let result = run_with_checkpoint(start, checkpoint, |start, save| {
+        let secure = |combination: &[usize]| combination.iter().all(|&b| b == 0);
+        resume_search(&space, &css, backend, degenerate, start, secure, save)
+    });
+    if let Some(&index) = result.selected.keys().next() {
+        let p = space.unrank(index).unwrap();
+        print_linicrypt(&AlgebraicRepresentation::<{ 4 + 2 }, 2, 1>::try_from(p).unwrap());
+    }
+}
+
+// Analyzes one program per class of programs that only differ by a change of basis
+// of the inputs, and weighs the results with the class sizes. Then also collapses
+// programs that only differ in the order of independent queries, and prints the
+// combinations of collision structures up to this symmetry.
+fn equivalence_classes_3_2_1(
+    backend: Backend,
+    queries: &[(Operation, usize)],
+    degenerate: &[DegeneracyKind],
+) {
+    println!();
+    println!(
+        "Counting the programs with 3 inputs, 2 queries and 1 output up to a change of basis."
+    );
+    // The programs are enumerated twice instead of being kept in memory.
+    let programs = || generate_programs::<Gf2, { 3 + 2 }, 2>(queries);
+    let classes = equivalence_classes(programs());
+    let num_programs: usize = classes.iter().map(|(_, size)| size).sum();
+    let css: Vec<_> = all_collision_structures(2).collect();
 
-    let mut counter: HashMap<String, usize> = HashMap::new();
-    let all_ids = css2
+    // One census counts the classes, the other the programs in them.
+    let (mut class_census, mut program_census) = (Census::new(&css), Census::new(&css));
+    for ((_, size), combination) in classes
         .iter()
-        .map(|cs| cs.id())
-        .chain(css1.iter().map(|cs| cs.id()));
-    for id in all_ids {
-        counter.insert(id, 0);
+        .zip(check_classes(&classes, &css, backend, degenerate))
+    {
+        match combination {
+            Some(combination) => {
+                class_census.record(&combination, 1);
+                program_census.record(&combination, *size);
+            }
+            None => {
+                class_census.record_degenerate(1);
+                program_census.record_degenerate(*size);
+            }
+        }
     }
+    println!("{num_programs} programs in {} classes.", classes.len());
+    println!(
+        "{} classes with {} programs are degenerate.",
+        class_census.degenerate, program_census.degenerate
+    );
+    println!(
+        "{} classes with {} programs have no collision structure.",
+        class_census.without_cs(),
+        program_census.without_cs()
+    );
 
-    let mut combination_counter: HashMap<Vec<usize>, usize> = HashMap::new();
-    let all_combinations = (0..12).into_iter().map(|_| 0..=1).multi_cartesian_product();
-    for comb in all_combinations {
-        combination_counter.insert(comb, 0);
+    let classes = symmetry_classes(programs());
+    println!(
+        "{} classes up to the order of independent queries.",
+        classes.len()
+    );
+    let css: Vec<_> = collision_structures(2, 2)
+        .chain(collision_structures(2, 1))
+        .collect();
+    println!("Swapping independent queries exchanges these collision structures.");
+    for cs in &css {
+        let swapped = cs.reorder_queries(&[1, 0]);
+        if cs.id() < swapped.id() {
+            println!("{} <-> {}", cs.id(), swapped.id());
+        }
     }
+    let mut census = Census::new(&css);
+    for ((_, size), combination) in classes
+        .iter()
+        .zip(check_classes(&classes, &css, backend, degenerate))
+    {
+        if let Some(combination) = combination {
+            census.record(&combination, *size);
+        }
+    }
+    println!("Up to this symmetry, only the combinations of the representatives remain.");
+    print_comb_counter(&census);
+}
 
-    let non_degenerate: Vec<_> = programs
-        .into_iter()
-        .filter(|p| !p.is_degenerate())
-        .collect();
+// The structures that apply to the representative of every class, on all cores.
+// `None` for degenerate representatives.
+fn check_classes<const BASE: usize, const N: usize>(
+    classes: &[(AlgebraicRepresentation<BASE, N, 1>, usize)],
+    css: &[DynCollisionStructure],
+    backend: Backend,
+    degenerate: &[DegeneracyKind],
+) -> Vec<Option<Vec<usize>>> {
+    classes
+        .par_iter()
+        .map(|(p, _)| {
+            (!has_degeneracy(p, degenerate, backend)).then(|| combination(p, css, backend))
+        })
+        .collect()
+}
 
-    let mut cells = vec![];
-    for p in &non_degenerate {
-        let mut cell = linicrypt_to_lines(p);
-        let (mut cs_2, mut info_2) = check_css(p, &css2, &mut counter);
-        cell.append(&mut info_2);
-        let (mut cs_1, mut info_1) = check_css(p, &css1, &mut counter);
-        cell.append(&mut info_1);
+// Runs the attacks on a lazily sampled cipher over GF(2^12). The programs with a
+// collision structure should all be broken with a few queries, the others only at
+// the rate of the birthday bound.
+fn empirical_validation(
+    backend: Backend,
+    queries: &[(Operation, usize)],
+    degenerate: &[DegeneracyKind],
+) {
+    const EVALUATIONS: usize = 16;
+    println!();
+    println!(
+        "Attacking all compression schemes with 2 input, 1 queries and 1 output over GF(2^12)."
+    );
+    let ps = generate_programs::<Gf2, { 2 + 1 }, 1>(queries);
+    let css: Vec<_> = all_collision_structures(1).collect();
 
-        let num_cs_2: usize = cs_2.iter().sum();
-        let num_cs_1: usize = cs_1.iter().sum();
+    let (mut attacked, mut most_queries) = (0, 0);
+    let (mut without_cs, mut broken) = (0, 0);
+    for (seed, p) in ps.enumerate() {
+        if has_degeneracy(&p, degenerate, backend) {
+            continue;
+        }
+        let p = p.lift::<Gf4096>();
+        let mut cipher = LazyCipher::new(seed as u64);
+        let mut rng = SplitMix64::new(seed as u64);
+        match css.iter().find_map(|cs| AttackPlan::new(&p, cs)) {
+            Some(plan) => {
+                let input = [rng.element(), rng.element()];
+                let collision = plan.execute(&p, &mut cipher, &input).unwrap();
+                let [first, second] = collision.inputs.map(|input| input.iter().join(","));
+                println!(
+                    "seed {seed}, {}: inputs {first} and {second} give {}",
+                    plan.cs_id,
+                    collision.output.iter().join(",")
+                );
+                attacked += 1;
+                most_queries = most_queries.max(cipher.num_queries());
+            }
+            None => {
+                without_cs += 1;
+                let collision = birthday_attack(&p, &mut cipher, &mut rng, EVALUATIONS).unwrap();
+                broken += collision.is_some() as usize;
+            }
+        }
+    }
+    println!("{attacked} programs with a collision structure, all broken with at most {most_queries} queries.");
+    println!(
+        "{broken} of {without_cs} programs without one broken with {EVALUATIONS} random evaluations,"
+    );
+    let birthday_bound = (EVALUATIONS * EVALUATIONS) as f64 / 2.0 / Gf4096::ORDER as f64;
+    println!("the birthday bound for a single program is {birthday_bound:.3}.");
+}
 
-        cs_2.append(&mut cs_1);
-        let combination_of_cs = cs_2;
-        *combination_counter.get_mut(&combination_of_cs).unwrap() += 1;
+// Prints the program with the given index among all programs with 3 inputs, 2 queries
+// and 1 output, and why the collision structure with the given id applies to it or not.
+fn explain(backend: Backend, queries: &[(Operation, usize)], index: &str, cs_id: &str) {
+    let space = space_from_arg("3", queries);
+    let p = program_from_arg(&space, index);
+    let cs: DynCollisionStructure = cs_id.parse().unwrap_or_else(|e| exit_with_error(e));
+    if cs.num_queries() != 2 {
+        exit_with_error("the programs make 2 queries");
+    }
+    print_linicrypt(&p);
+    println!("{}", p.explain_cs_with(&cs, backend));
+}
 
-        if num_cs_2 + num_cs_1 <= 2 {
-            cells.push(cell);
-        }
+// Prints the program with the given index in the given space, without enumerating the
+// programs before it, and why it is degenerate.
+fn show(backend: Backend, queries: &[(Operation, usize)], space: &str, index: &str) {
+    let space = space_from_arg(space, queries);
+    let p = program_from_arg(&space, index);
+    let (inputs, queries, outputs) = (space.inputs, space.queries, space.outputs);
+    println!("Program #{index} of {inputs}-{queries}-{outputs}:");
+    print_linicrypt(&p);
+    print!("{}", to_text(&p).unwrap());
+    for reason in degeneracies(&p, backend).unwrap() {
+        println!("degenerate: {reason}");
     }
+}
 
-    print_grid(cells, 8);
-    println!("{:#?}", counter);
-    print_comb_counter(&css2, &css1, combination_counter);
+// Counts the collision structures of all programs of the given space on all cores,
+// without keeping the programs in memory.
+// Pass `--range <start>..<end>` to only count the programs with these indices,
+// `--checkpoint <file>` to save the state to the file every minute, and `--resume`
+// to continue from the state saved there.
+fn census_of_space(
+    backend: Backend,
+    queries: &[(Operation, usize)],
+    degenerate: &[DegeneracyKind],
+    space: &str,
+) {
+    let space = space_from_arg(space, queries);
+    let n = space.num_programs();
+    let range = flag_value("--range").map_or(0..n, |range| range_from_arg(&range, n));
+    let css: Vec<_> = (1..=space.queries)
+        .rev()
+        .flat_map(|different| collision_structures(space.queries, different))
+        .collect();
+    let shape = shape_description(&space);
+    let description = run_description(&space, backend, degenerate);
+    if range == (0..n) {
+        println!("Counting the collision structures of all {n} programs with {shape}.");
+    } else {
+        println!(
+            "Counting the collision structures of the programs {}..{} of the {n} programs with {shape}.",
+            range.start, range.end
+        );
+    }
+    let checkpoint = flag_value("--checkpoint").map(PathBuf::from);
+    let start = Checkpoint::new(description, range, &css);
+    let result = run_with_checkpoint(start, checkpoint, |start, save| {
+        resume_census(&space, &css, backend, degenerate, start, |_| false, save)
+    });
+    let census = result.census;
+    println!(
+        "{} programs are degenerate, {} others have no collision structure.",
+        census.degenerate,
+        census.without_cs()
+    );
+    print_cs_counts(&census);
+    print_comb_counter(&census);
 }
 
-fn secure_4_2_1() {
-    println!();
+// Reads a program in the text language of `linicrypt::dsl`, from the file with the
+// given name or from the argument itself, and prints why it is degenerate and which
+// collision structures apply to it. The argument is only taken as the program if there
+// is no such file and it has a `;`, a space or a line break, like every program, so a
+// mistyped file name is reported as such.
+fn analyze(backend: Backend, program: &str) {
+    let text = match std::fs::read_to_string(program) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound && program.contains([';', ' ', '\n']) => {
+            program.to_string()
+        }
+        Err(e) => exit_with_error(format!("cannot read {program}: {e}")),
+    };
+    let p = parse::<Gf2>(&text).unwrap_or_else(|e| exit_with_error(e));
+    print_linicrypt(&p);
+    for reason in degeneracies(&p, backend).unwrap() {
+        println!("degenerate: {reason}");
+    }
+    for cs in all_collision_structures(p.constraints.len()) {
+        let applies = if p.has_cs_with(&cs, backend) {
+            "Y"
+        } else {
+            " "
+        };
+        println!("{applies}{}", cs.id());
+    }
+}
+
+// Counts the programs of the given space with each kind of degeneracy, and with each
+// set of kinds that occurs together.
+fn degeneracy_counts(backend: Backend, queries: &[(Operation, usize)], space: &str) {
+    let space = space_from_arg(space, queries);
     println!(
-        "Finding a program with 4 inputs, making 2 queries to E without a collision structure"
+        "Classifying the degeneracies of all {} programs with {}.",
+        space.num_programs(),
+        shape_description(&space)
     );
-    let ps = generate_i_2_1_programs::<{ 4 + 2 }>();
-    let css2: Vec<_> = generate_all_cs_2::<2>().collect();
-    let css1: Vec<_> = generate_all_cs_2::<1>().collect();
-    for p in &ps {
-        if p.is_degenerate() {
-            continue;
+    let mut per_kind = BTreeMap::new();
+    let mut per_set = BTreeMap::new();
+    check_in_order(
+        &space,
+        |p| {
+            let reasons = degeneracies(p, backend).unwrap();
+            reasons
+                .iter()
+                .map(|reason| reason.kind())
+                .dedup()
+                .collect_vec()
+        },
+        |_, kinds| {
+            for &kind in &kinds {
+                *per_kind.entry(kind).or_insert(0) += 1;
+            }
+            *per_set.entry(kinds).or_insert(0) += 1;
+        },
+    );
+    for kind in DegeneracyKind::ALL {
+        println!("{kind}: {}", per_kind.get(&kind).unwrap_or(&0));
+    }
+    println!("These sets of degeneracies occured.");
+    for (kinds, count) in per_set {
+        let kinds = if kinds.is_empty() {
+            "none".to_string()
+        } else {
+            kinds.iter().join(", ")
+        };
+        println!("{kinds}: {count}");
+    }
+}
+
+fn shape_description(space: &ProgramSpace<Gf2>) -> String {
+    format!(
+        "{} inputs, {} queries and {} output{}",
+        space.inputs,
+        space.queries,
+        space.outputs,
+        if space.outputs == 1 { "" } else { "s" }
+    )
+}
+
+// Does the part of `collision_structure_examples` for the programs with the given
+// indices, and saves the result to the file every minute and when it is done. `merge`
+// then combines the results of shards for all programs into the same output.
+fn shard(
+    backend: Backend,
+    queries: &[(Operation, usize)],
+    degenerate: &[DegeneracyKind],
+    range: &str,
+    path: &str,
+) {
+    let (space, css, description) = examples_run(backend, queries, degenerate);
+    let range = range_from_arg(range, space.num_programs());
+    eprintln!(
+        "Checking the programs {}..{} for the examples.",
+        range.start, range.end
+    );
+    let start = Checkpoint::new(description, range, &css);
+    let path = Some(path.into());
+    run_with_checkpoint(start, path, |start, save| {
+        resume_census(&space, &css, backend, degenerate, start, is_example, save)
+    });
+}
+
+// Prints the output of `collision_structure_examples` from the results of shards.
+fn merge(
+    backend: Backend,
+    queries: &[(Operation, usize)],
+    degenerate: &[DegeneracyKind],
+    paths: &[String],
+) {
+    let (space, _, description) = examples_run(backend, queries, degenerate);
+    let shards = paths
+        .iter()
+        .map(|path| {
+            Checkpoint::load(path.as_ref())
+                .unwrap_or_else(|e| exit_with_error(format!("{path}: {e}")))
+        })
+        .collect();
+    let result = merge_shards(shards).unwrap_or_else(|e| exit_with_error(e));
+    if result.description != description {
+        exit_with_error(format!("the shards are for {}", result.description));
+    }
+    if result.range != (0..space.num_programs()) {
+        exit_with_error(format!(
+            "the shards only cover the programs {}..{} of {}",
+            result.range.start,
+            result.range.end,
+            space.num_programs()
+        ));
+    }
+    print_examples(&space, &result);
+}
+
+// Runs `run` from `start`, and if a file is given, saves the states `run` hands to
+// `save` there every minute and the final state when it is done. With `--resume` it
+// continues from the state saved there instead, which has to be for the same run and
+// range.
+fn run_with_checkpoint(
+    start: Checkpoint,
+    checkpoint: Option<PathBuf>,
+    run: impl FnOnce(Checkpoint, &mut dyn FnMut(&Checkpoint)) -> Checkpoint,
+) -> Checkpoint {
+    let start = match (&checkpoint, flag("--resume")) {
+        (Some(path), true) => {
+            let saved = Checkpoint::load(path).unwrap_or_else(|e| exit_with_error(e));
+            if (&saved.description, &saved.range) != (&start.description, &start.range) {
+                exit_with_error(format!(
+                    "the checkpoint is for the programs {}..{} of {}",
+                    saved.range.start, saved.range.end, saved.description
+                ));
+            }
+            eprintln!("Resuming at program {}.", saved.position);
+            saved
         }
-        let has_cs_1 = css1.iter().any(|cs| p.has_cs(cs));
-        let has_cs_2 = css2.iter().any(|cs| p.has_cs(cs));
-        if !has_cs_1 && !has_cs_2 {
-            print_linicrypt(p);
-            break;
+        (None, true) => exit_with_error("--resume needs --checkpoint <file>"),
+        (_, false) => start,
+    };
+    let mut last_save = Instant::now();
+    let result = run(start, &mut |state| {
+        let Some(path) = &checkpoint else { return };
+        if last_save.elapsed() >= Duration::from_secs(60) {
+            state.save(path).unwrap_or_else(|e| exit_with_error(e));
+            last_save = Instant::now();
         }
+    });
+    if let Some(path) = &checkpoint {
+        result.save(path).unwrap_or_else(|e| exit_with_error(e));
+    }
+    result
+}
+
+// The programs with the given number of inputs, 2 queries and 1 output that uses the
+// answer of the last query, or with the shape `<inputs>-<queries>-<outputs>`.
+fn space_from_arg(arg: &str, queries: &[(Operation, usize)]) -> ProgramSpace<Gf2> {
+    let numbers: Option<Vec<usize>> = arg.split('-').map(|n| n.parse().ok()).collect();
+    let (inputs, num_queries, outputs) = match numbers.as_deref() {
+        Some(&[inputs]) => (inputs, 2, 1),
+        Some(&[inputs, num_queries, outputs]) => (inputs, num_queries, outputs),
+        _ => exit_with_error(format!(
+            "{arg:?} is neither a number of inputs nor a shape like 4-2-1"
+        )),
+    };
+    let space = ProgramSpace::new(inputs, num_queries, outputs)
+        .with_operations(queries)
+        .with_last_answer_in_output();
+    if space.checked_num_programs().is_none() {
+        exit_with_error(format!(
+            "the space {arg} has too many programs to number them"
+        ));
     }
+    space
+}
+
+fn program_from_arg(space: &ProgramSpace<Gf2>, index: &str) -> DynAlgebraicRepresentation<Gf2> {
+    match index.parse().ok().and_then(|i| space.unrank(i)) {
+        Some(p) => p,
+        None => exit_with_error(format!(
+            "the index has to be a number below {}",
+            space.num_programs()
+        )),
+    }
+}
+
+// A range `<start>..<end>` or `<start>..` of the indices of `n` programs.
+fn range_from_arg(arg: &str, n: u128) -> std::ops::Range<u128> {
+    let range = arg.split_once("..").and_then(|(start, end)| {
+        let start = start.parse().ok()?;
+        let end = if end.is_empty() { n } else { end.parse().ok()? };
+        (start <= end && end <= n).then_some(start..end)
+    });
+    range.unwrap_or_else(|| exit_with_error(format!("{arg:?} is not a range of indices below {n}")))
+}
+
+// The flags that take the next argument as their value, and the ones without a value.
+const VALUE_FLAGS: [&str; 4] = ["--oracles", "--degenerate", "--range", "--checkpoint"];
+const FLAGS: [&str; 5] = [
+    "--real",
+    "--decryption",
+    "--resume",
+    "--classes",
+    "--empirical",
+];
+
+// The command line, with the flags and their values apart from the other arguments,
+// like the subcommand and its operands.
+#[derive(Debug, Default, PartialEq)]
+struct Args {
+    positional: Vec<String>,
+    flags: Vec<(String, Option<String>)>,
+}
+
+impl Args {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if VALUE_FLAGS.contains(&arg.as_str()) {
+                let value = args.next().ok_or(format!("{arg} needs a value"))?;
+                parsed.flags.push((arg, Some(value)));
+            } else if FLAGS.contains(&arg.as_str()) {
+                parsed.flags.push((arg, None));
+            } else if arg.starts_with("--") {
+                return Err(format!("unknown flag {arg}"));
+            } else {
+                parsed.positional.push(arg);
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+// The arguments of this process, parsed once.
+fn args() -> &'static Args {
+    static ARGS: OnceLock<Args> = OnceLock::new();
+    ARGS.get_or_init(|| {
+        Args::parse(std::env::args().skip(1)).unwrap_or_else(|e| exit_with_error(e))
+    })
+}
+
+// Whether the flag `name` was passed.
+fn flag(name: &str) -> bool {
+    args().flags.iter().any(|(flag, _)| flag == name)
+}
+
+// The value of the flag `name`, the last one if it was passed several times.
+fn flag_value(name: &str) -> Option<String> {
+    let mut flags = args().flags.iter().rev();
+    flags.find(|(flag, _)| flag == name)?.1.clone()
+}
+
+fn exit_with_error(message: impl std::fmt::Display) -> ! {
+    eprintln!("error: {message}");
+    std::process::exit(1)
+}
+
+// Pass `--degenerate <kinds>` to leave out the programs with any of these kinds of
+// degeneracies, separated by commas, instead of the rank deficient ones.
+fn degenerate_from_args() -> Vec<DegeneracyKind> {
+    match flag_value("--degenerate") {
+        Some(kinds) => kinds
+            .split(',')
+            .map(|kind| kind.parse().unwrap_or_else(|e| exit_with_error(e)))
+            .collect(),
+        None => vec![DegeneracyKind::RankDeficient],
+    }
+}
+
+// Pass `--real` to use the old floating point rank computation instead of GF(2).
+fn backend_from_args() -> Backend {
+    if flag("--real") {
+        Backend::Real
+    } else {
+        Backend::Exact
+    }
+}
+
+// Pass `--decryption` to also enumerate programs that query the cipher with D, and
+// `--oracles <n>` to let every query go to one of n independent ciphers.
+fn queries_from_args() -> Vec<(Operation, usize)> {
+    let ops = if flag("--decryption") {
+        vec![Operation::E, Operation::D]
+    } else {
+        vec![Operation::E]
+    };
+    let oracles = match flag_value("--oracles") {
+        Some(n) => n
+            .parse()
+            .unwrap_or_else(|_| exit_with_error(format!("--oracles needs a number, not {n:?}"))),
+        None => 1,
+    };
+    iproduct!(0..oracles, ops)
+        .map(|(oracle, op)| (op, oracle))
+        .collect()
 }
 
 fn main() {
-    compression_functions();
-    collision_structure_examples();
-    secure_4_2_1();
+    let backend = backend_from_args();
+    let queries = queries_from_args();
+    let degenerate = degenerate_from_args();
+    let args = &args().positional;
+    match args.first().map(String::as_str) {
+        Some("explain") => {
+            match &args[1..] {
+                [index, cs_id, ..] => explain(backend, &queries, index, cs_id),
+                _ => exit_with_error("usage: explain <program index> <collision structure id>"),
+            }
+            return;
+        }
+        Some("census") => {
+            match &args[1..] {
+                [space, ..] => census_of_space(backend, &queries, &degenerate, space),
+                _ => exit_with_error("usage: census <number of inputs or shape like 4-2-1>"),
+            }
+            return;
+        }
+        Some("shard") => {
+            match &args[1..] {
+                [range, path, ..] => shard(backend, &queries, &degenerate, range, path),
+                _ => exit_with_error("usage: shard <start>..<end> <file>"),
+            }
+            return;
+        }
+        Some("merge") => {
+            merge(backend, &queries, &degenerate, &args[1..]);
+            return;
+        }
+        Some("show") => {
+            match &args[1..] {
+                [space, index, ..] => show(backend, &queries, space, index),
+                _ => exit_with_error("usage: show <number of inputs or shape like 4-2-1> <index>"),
+            }
+            return;
+        }
+        Some("analyze") => {
+            match &args[1..] {
+                [program, ..] => analyze(backend, program),
+                _ => exit_with_error("usage: analyze <program or file with a program>"),
+            }
+            return;
+        }
+        Some("degeneracy") => {
+            match &args[1..] {
+                [space, ..] => degeneracy_counts(backend, &queries, space),
+                _ => exit_with_error("usage: degeneracy <number of inputs or shape like 4-2-1>"),
+            }
+            return;
+        }
+        _ => {}
+    }
+    compression_functions(backend, &queries);
+    collision_structure_examples(backend, &queries, &degenerate);
+    let checkpoint = flag_value("--checkpoint").map(PathBuf::from);
+    secure_4_2_1(backend, &queries, &degenerate, checkpoint);
+    // Pass `--classes` to also count the programs up to a change of basis.
+    if flag("--classes") {
+        equivalence_classes_3_2_1(backend, &queries, &degenerate);
+    }
+    // Pass `--empirical` to also run the attacks on a concrete cipher.
+    if flag("--empirical") {
+        empirical_validation(backend, &queries, &degenerate);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use linicrypt::Constraint;
+    use nalgebra::{RowVector3, RowVector5, RowVector6};
+
+    #[test]
+    fn check_args() {
+        let parse = |args: &[&str]| Args::parse(args.iter().map(|arg| arg.to_string()));
+        let args = parse(&[
+            "merge",
+            "--checkpoint",
+            "run.checkpoint",
+            "first.shard",
+            "--real",
+            "--range",
+            "0..5",
+            "second.shard",
+        ])
+        .unwrap();
+        assert_eq!(args.positional, ["merge", "first.shard", "second.shard"]);
+        assert_eq!(
+            args.flags,
+            [
+                (
+                    "--checkpoint".to_string(),
+                    Some("run.checkpoint".to_string())
+                ),
+                ("--real".to_string(), None),
+                ("--range".to_string(), Some("0..5".to_string())),
+            ]
+        );
+        assert!(parse(&["census", "2", "--oracles"]).is_err());
+        assert!(parse(&["census", "2", "--orcales", "2"]).is_err());
+    }
 
     #[test]
     fn check_all_cs_2_2() {
-        use super::Direction::*;
-        let manual = vec![
+        use linicrypt::CollisionStructure;
+        use linicrypt::Direction::*;
+        let manual = [
             CollisionStructure::<2, 2> {
                 permutation: [0, 1],
                 cs_type: [F, F],
@@ -305,18 +914,15 @@ mod tests {
                 cs_type: [B, B],
             },
         ];
-        let automatic = generate_all_cs_2::<2>();
-        for (m, a) in manual.iter().zip(automatic) {
-            println!("{:?}", a);
-            println!("{:?}", m);
-            assert_eq!(a, *m);
-        }
+        let automatic: Vec<_> = collision_structures(2, 2).collect();
+        assert_eq!(automatic, manual.map(|m| DynCollisionStructure::from(&m)));
     }
 
     #[test]
     fn check_all_cs_2_1() {
-        use super::Direction::*;
-        let manual = vec![
+        use linicrypt::CollisionStructure;
+        use linicrypt::Direction::*;
+        let manual = [
             CollisionStructure::<2, 1> {
                 permutation: [0, 1],
                 cs_type: [F],
@@ -334,78 +940,200 @@ mod tests {
                 cs_type: [B],
             },
         ];
-        let automatic = generate_all_cs_2::<1>();
-        for (m, a) in manual.iter().zip(automatic) {
-            println!("{:?}", a);
-            println!("{:?}", m);
-            assert_eq!(a, *m);
-        }
+        let automatic: Vec<_> = collision_structures(2, 1).collect();
+        assert_eq!(automatic, manual.map(|m| DynCollisionStructure::from(&m)));
+    }
+
+    fn nth_program<F: Field, const BASE: usize, const N: usize>(
+        queries: &[(Operation, usize)],
+        n: usize,
+    ) -> AlgebraicRepresentation<BASE, N, 1, F> {
+        let space = ProgramSpace::<F>::new(BASE - N, N, 1)
+            .with_operations(queries)
+            .with_last_answer_in_output();
+        AlgebraicRepresentation::try_from(space.iter().nth(n).unwrap()).unwrap()
     }
 
     #[test]
     fn check_generate_constraints_5() {
-        let mut cs = generate_all_constraints::<5, 1>();
+        let queries = [(Operation::E, 0)];
         let manual_c = Constraint {
             op: Operation::E,
-            k: RowVector5::new(0, 0, 0, 0, 0),
-            x: RowVector5::new(0, 0, 0, 0, 0),
-            y: RowVector5::new(0, 0, 0, 0, 1),
+            oracle: 0,
+            k: RowVector5::new(0, 0, 0, 0, 0).map(Gf2::from),
+            x: RowVector5::new(0, 0, 0, 0, 0).map(Gf2::from),
+            y: RowVector5::new(0, 0, 0, 0, 1).map(Gf2::from),
         };
-        assert_eq!(cs.next(), Some(manual_c));
+        assert_eq!(
+            nth_program::<Gf2, 5, 2>(&queries, 0).constraints[1],
+            manual_c
+        );
         let manual_c = Constraint {
             op: Operation::E,
-            k: RowVector5::new(0, 0, 0, 0, 0),
-            x: RowVector5::new(0, 0, 0, 1, 0),
-            y: RowVector5::new(0, 0, 0, 0, 1),
+            oracle: 0,
+            k: RowVector5::new(0, 0, 0, 0, 0).map(Gf2::from),
+            x: RowVector5::new(0, 0, 0, 1, 0).map(Gf2::from),
+            y: RowVector5::new(0, 0, 0, 0, 1).map(Gf2::from),
         };
-        assert_eq!(cs.next(), Some(manual_c));
-        let mut cs = generate_all_constraints::<5, 2>();
+        assert_eq!(
+            nth_program::<Gf2, 5, 2>(&queries, 1).constraints[1],
+            manual_c
+        );
         let manual_c = Constraint {
             op: Operation::E,
-            k: RowVector5::new(0, 0, 0, 0, 0),
-            x: RowVector5::new(0, 0, 0, 0, 0),
-            y: RowVector5::new(0, 0, 0, 1, 0),
+            oracle: 0,
+            k: RowVector5::new(0, 0, 0, 0, 0).map(Gf2::from),
+            x: RowVector5::new(0, 0, 0, 0, 0).map(Gf2::from),
+            y: RowVector5::new(0, 0, 0, 1, 0).map(Gf2::from),
         };
-        assert_eq!(cs.next(), Some(manual_c));
+        assert_eq!(
+            nth_program::<Gf2, 5, 2>(&queries, 0).constraints[0],
+            manual_c
+        );
+        // The second query has 2^4 keys and 2^4 plaintexts.
         let manual_c = Constraint {
             op: Operation::E,
-            k: RowVector5::new(0, 0, 0, 0, 0),
-            x: RowVector5::new(0, 0, 1, 0, 0),
-            y: RowVector5::new(0, 0, 0, 1, 0),
+            oracle: 0,
+            k: RowVector5::new(0, 0, 0, 0, 0).map(Gf2::from),
+            x: RowVector5::new(0, 0, 1, 0, 0).map(Gf2::from),
+            y: RowVector5::new(0, 0, 0, 1, 0).map(Gf2::from),
         };
-        assert_eq!(cs.next(), Some(manual_c));
+        assert_eq!(
+            nth_program::<Gf2, 5, 2>(&queries, 256).constraints[0],
+            manual_c
+        );
     }
     #[test]
     fn check_generate_constraints_6() {
-        let mut cs = generate_all_constraints::<6, 1>();
+        let queries = [(Operation::E, 0)];
         let manual_c = Constraint {
             op: Operation::E,
-            k: RowVector6::new(0, 0, 0, 0, 0, 0),
-            x: RowVector6::new(0, 0, 0, 0, 0, 0),
-            y: RowVector6::new(0, 0, 0, 0, 0, 1),
+            oracle: 0,
+            k: RowVector6::new(0, 0, 0, 0, 0, 0).map(Gf2::from),
+            x: RowVector6::new(0, 0, 0, 0, 0, 0).map(Gf2::from),
+            y: RowVector6::new(0, 0, 0, 0, 0, 1).map(Gf2::from),
         };
-        assert_eq!(cs.next(), Some(manual_c));
+        assert_eq!(
+            nth_program::<Gf2, 6, 2>(&queries, 0).constraints[1],
+            manual_c
+        );
         let manual_c = Constraint {
             op: Operation::E,
-            k: RowVector6::new(0, 0, 0, 0, 0, 0),
-            x: RowVector6::new(0, 0, 0, 0, 1, 0),
-            y: RowVector6::new(0, 0, 0, 0, 0, 1),
+            oracle: 0,
+            k: RowVector6::new(0, 0, 0, 0, 0, 0).map(Gf2::from),
+            x: RowVector6::new(0, 0, 0, 0, 1, 0).map(Gf2::from),
+            y: RowVector6::new(0, 0, 0, 0, 0, 1).map(Gf2::from),
         };
-        assert_eq!(cs.next(), Some(manual_c));
-        let mut cs = generate_all_constraints::<6, 2>();
+        assert_eq!(
+            nth_program::<Gf2, 6, 2>(&queries, 1).constraints[1],
+            manual_c
+        );
         let manual_c = Constraint {
             op: Operation::E,
-            k: RowVector6::new(0, 0, 0, 0, 0, 0),
-            x: RowVector6::new(0, 0, 0, 0, 0, 0),
-            y: RowVector6::new(0, 0, 0, 0, 1, 0),
+            oracle: 0,
+            k: RowVector6::new(0, 0, 0, 0, 0, 0).map(Gf2::from),
+            x: RowVector6::new(0, 0, 0, 0, 0, 0).map(Gf2::from),
+            y: RowVector6::new(0, 0, 0, 0, 1, 0).map(Gf2::from),
         };
-        assert_eq!(cs.next(), Some(manual_c));
+        assert_eq!(
+            nth_program::<Gf2, 6, 2>(&queries, 0).constraints[0],
+            manual_c
+        );
+        // The second query has 2^5 keys and 2^5 plaintexts.
         let manual_c = Constraint {
             op: Operation::E,
-            k: RowVector6::new(0, 0, 0, 0, 0, 0),
-            x: RowVector6::new(0, 0, 0, 1, 0, 0),
-            y: RowVector6::new(0, 0, 0, 0, 1, 0),
+            oracle: 0,
+            k: RowVector6::new(0, 0, 0, 0, 0, 0).map(Gf2::from),
+            x: RowVector6::new(0, 0, 0, 1, 0, 0).map(Gf2::from),
+            y: RowVector6::new(0, 0, 0, 0, 1, 0).map(Gf2::from),
         };
-        assert_eq!(cs.next(), Some(manual_c));
+        assert_eq!(
+            nth_program::<Gf2, 6, 2>(&queries, 1024).constraints[0],
+            manual_c
+        );
+    }
+
+    #[test]
+    fn check_generators_over_gf3() {
+        use linicrypt::field::Gf3;
+        // 3^2 output vectors, 3^2 keys and 3^2 plaintexts.
+        assert_eq!(
+            generate_programs::<Gf3, 3, 1>(&[(Operation::E, 0)]).count(),
+            729
+        );
+        assert_eq!(
+            nth_program::<Gf3, 3, 1>(&[(Operation::E, 0)], 1).constraints[0].x,
+            RowVector3::new(0, 1, 0).map(Gf3::from_u64)
+        );
+    }
+
+    #[test]
+    fn check_lines_with_two_outputs() {
+        use Operation::*;
+        let p = AlgebraicRepresentation::<5, 2, 2>::with_outputs(
+            [[0, 1, 0, 1, 0], [0, 0, 1, 0, 1]],
+            [
+                (E, [1, 0, 0, 0, 0], [0, 1, 0, 0, 0], [0, 0, 0, 1, 0]),
+                (E, [1, 0, 0, 0, 0], [0, 0, 1, 0, 0], [0, 0, 0, 0, 1]),
+            ],
+        );
+        let lines = linicrypt_to_lines(&p);
+        assert_eq!(lines[..3], ["0M=01010", "1M=00101", "0k=10000"]);
+    }
+
+    #[test]
+    fn check_generate_decryption_constraints() {
+        use Operation::*;
+        let [e, d] = [0, 1].map(|n| {
+            let [c] = nth_program::<Gf2, 3, 1>(&[(E, 0), (D, 0)], n).constraints;
+            c
+        });
+        assert_eq!((e.op, d.op), (E, D));
+        assert_eq!((e.k, e.x, e.y), (d.k, d.x, d.y));
+        assert_eq!(
+            generate_programs::<Gf2, 3, 1>(&[(E, 0), (D, 0)]).count(),
+            128
+        );
+    }
+
+    #[test]
+    fn check_generate_constraints_for_two_oracles() {
+        use Operation::*;
+        let queries = [(E, 0), (E, 1)];
+        let [c] = nth_program::<Gf2, 3, 1>(&queries, 0).constraints;
+        assert_eq!(c.oracle, 0);
+        let [c] = nth_program::<Gf2, 3, 1>(&queries, 1).constraints;
+        assert_eq!((c.op, c.oracle), (E, 1));
+        assert_eq!(generate_programs::<Gf2, 3, 1>(&queries).count(), 128);
+
+        let p = AlgebraicRepresentation::<5, 2, 1>::new(
+            [0, 0, 1, 1, 1],
+            [
+                (E, [1, 0, 0, 0, 0], [0, 1, 0, 0, 0], [0, 0, 0, 1, 0]),
+                (D, [1, 0, 0, 0, 0], [0, 1, 0, 0, 0], [0, 0, 0, 0, 1]),
+            ],
+        )
+        .with_oracles([0, 1]);
+        let lines = linicrypt_to_lines(&p);
+        assert_eq!(lines[3], "0y=00010");
+        assert_eq!(lines[6], "1y=00001 D1");
+    }
+
+    #[test]
+    fn check_classes_of_compression_functions() {
+        let queries = [(Operation::E, 0), (Operation::D, 0)];
+        let programs: Vec<_> = generate_programs::<Gf2, { 2 + 1 }, 1>(&queries).collect();
+        let classes = equivalence_classes(programs.clone());
+        let sizes: usize = classes.iter().map(|(_, size)| size).sum();
+        assert_eq!(sizes, programs.len());
+        let css: Vec<_> = collision_structures(1, 1).collect();
+        for p in &programs {
+            let canonical = p.canonical_form();
+            assert!(classes.iter().any(|(q, _)| *q == canonical));
+            assert_eq!(p.is_degenerate(), canonical.is_degenerate());
+            for cs in &css {
+                assert_eq!(p.has_cs(cs), canonical.has_cs(cs));
+            }
+        }
     }
 }
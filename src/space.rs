@@ -0,0 +1,390 @@
+//! Enumeration of all programs of a given shape.
+//!
+//! A program in the space has `inputs` inputs followed by the answers of its
+//! `queries` queries in the base vector, query `i` writes its answer right after the
+//! answers of the queries before it. The `k` and `x` rows of query `i` range over all
+//! rows using only the inputs and the answers of the queries before `i`, so every
+//! program is valid. The output rows range over all rows.
+//!
+//! The programs are enumerated like an odometer: the entries of the output rows change
+//! slowest, then those of `k` and `x` of the first query and its operation, then those
+//! of the second query, and so on. Within a row the last entry changes fastest.
+//! This is the lexicographic order of the programs, and `ProgramSpace::rank` and
+//! `ProgramSpace::unrank` map between a program and its index in it.
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use itertools::Itertools;
+
+use crate::dynamic::{DynAlgebraicRepresentation, DynConstraint, Shape};
+use crate::field::{Field, Gf2};
+use crate::{Operation, Program};
+
+/// The programs with a given number of inputs, queries and outputs over the field `F`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramSpace<F: Field = Gf2> {
+    pub inputs: usize,
+    pub queries: usize,
+    pub outputs: usize,
+    /// The operations and oracles every query chooses from, in this order.
+    pub operations: Vec<(Operation, usize)>,
+    /// Only programs whose first output row has coefficient one for the answer of the
+    /// last query. A program that does not use this answer makes a useless query.
+    pub last_answer_in_output: bool,
+    field: PhantomData<F>,
+}
+
+impl<F: Field> ProgramSpace<F> {
+    /// All programs of this shape that only encrypt with oracle `0`.
+    pub fn new(inputs: usize, queries: usize, outputs: usize) -> Self {
+        ProgramSpace {
+            inputs,
+            queries,
+            outputs,
+            operations: vec![(Operation::E, 0)],
+            last_answer_in_output: false,
+            field: PhantomData,
+        }
+    }
+
+    /// Lets every query choose from `operations` instead of only encrypting.
+    pub fn with_operations(mut self, operations: &[(Operation, usize)]) -> Self {
+        self.operations = operations.to_vec();
+        self
+    }
+
+    /// Restricts the space to the programs whose first output uses the answer of the
+    /// last query with coefficient one, like the original experiments did.
+    pub fn with_last_answer_in_output(mut self) -> Self {
+        self.last_answer_in_output = true;
+        self
+    }
+
+    pub fn shape(&self) -> Shape {
+        Shape {
+            base: self.inputs + self.queries,
+            queries: self.queries,
+            outputs: self.outputs,
+        }
+    }
+
+    /// The number of programs in the space.
+    ///
+    /// # Panics
+    /// If it does not fit into a `u128`, see `checked_num_programs`.
+    pub fn num_programs(&self) -> u128 {
+        self.checked_num_programs().expect("too many programs")
+    }
+
+    /// The number of programs in the space, `None` if it does not fit into a `u128`.
+    pub fn checked_num_programs(&self) -> Option<u128> {
+        self.radices()
+            .iter()
+            .try_fold(1u128, |n, &radix| n.checked_mul(radix.into()))
+    }
+
+    /// Iterates over all programs of the space, in the order described in the module
+    /// documentation. The programs are built one at a time.
+    pub fn iter(&self) -> Programs<F> {
+        self.iter_from(0)
+    }
+
+    /// Like `iter`, but skips the first `position` programs without building them.
+    pub fn iter_from(&self, position: u128) -> Programs<F> {
+        let radices = self.radices();
+        let (digits, done) = match self.digits(position) {
+            Some(digits) => (digits, false),
+            None => (vec![0; radices.len()], true),
+        };
+        Programs {
+            space: self.clone(),
+            radices,
+            digits,
+            done,
+        }
+    }
+
+    /// The program with the given index in the order of `iter`, built without
+    /// enumerating the programs before it. `None` if there are not that many programs.
+    pub fn unrank(&self, index: u128) -> Option<DynAlgebraicRepresentation<F>> {
+        Some(self.program(&self.digits(index)?))
+    }
+
+    /// The index of `p` in the order of `iter`, the inverse of `unrank`. `None` if `p`
+    /// is not a program of this space, or if its index does not fit into a `u128`.
+    pub fn rank(&self, p: &impl Program<F>) -> Option<u128> {
+        if p.base() != self.inputs + self.queries
+            || p.num_queries() != self.queries
+            || p.num_outputs() != self.outputs
+        {
+            return None;
+        }
+        let base = p.base();
+        let mut digits = vec![];
+        for j in 0..self.outputs {
+            let row = p.output(j);
+            if j == 0 && self.last_answer_in_output && self.queries > 0 {
+                if row[base - 1] != F::ONE {
+                    return None;
+                }
+                push_row(&mut digits, &row[..base - 1], base - 1)?;
+            } else {
+                push_row(&mut digits, &row, base)?;
+            }
+        }
+        for i in 0..self.queries {
+            let q = p.query(i);
+            let answer = self.inputs + i;
+            let y_is_answer =
+                q.y.iter()
+                    .enumerate()
+                    .all(|(l, &e)| e == if l == answer { F::ONE } else { F::ZERO });
+            if !y_is_answer {
+                return None;
+            }
+            push_row(&mut digits, q.k, answer)?;
+            push_row(&mut digits, q.x, answer)?;
+            let op = self
+                .operations
+                .iter()
+                .position(|&o| o == (q.op, q.oracle))?;
+            digits.push(op as u64);
+        }
+        digits
+            .iter()
+            .zip(&self.radices())
+            .try_fold(0u128, |index, (&digit, &radix)| {
+                index.checked_mul(radix.into())?.checked_add(digit.into())
+            })
+    }
+
+    // The digits of the odometer after `position` steps, `None` if it has stopped by then.
+    fn digits(&self, position: u128) -> Option<Vec<u64>> {
+        let radices = self.radices();
+        if radices.contains(&0) {
+            return None;
+        }
+        let mut digits = vec![0; radices.len()];
+        let mut rest = position;
+        for (digit, &radix) in digits.iter_mut().zip(&radices).rev() {
+            *digit = (rest % radix as u128) as u64;
+            rest /= radix as u128;
+        }
+        (rest == 0).then_some(digits)
+    }
+
+    // The number of choices for every digit of the odometer, slowest first.
+    fn radices(&self) -> Vec<u64> {
+        let base = self.inputs + self.queries;
+        let mut radices = vec![];
+        for j in 0..self.outputs {
+            let fixed = (j == 0 && self.last_answer_in_output && self.queries > 0) as usize;
+            radices.extend(std::iter::repeat_n(F::ORDER, base - fixed));
+        }
+        for i in 0..self.queries {
+            radices.extend(std::iter::repeat_n(F::ORDER, 2 * (self.inputs + i)));
+            radices.push(self.operations.len() as u64);
+        }
+        radices
+    }
+
+    // The program the digits of the odometer stand for.
+    fn program(&self, digits: &[u64]) -> DynAlgebraicRepresentation<F> {
+        let base = self.inputs + self.queries;
+        let mut digits = digits.iter().copied();
+        let mut m = vec![];
+        for j in 0..self.outputs {
+            if j == 0 && self.last_answer_in_output && self.queries > 0 {
+                let mut row = take_row(&mut digits, base - 1, base);
+                row[base - 1] = F::ONE;
+                m.push(row);
+            } else {
+                m.push(take_row(&mut digits, base, base));
+            }
+        }
+        let constraints = (0..self.queries)
+            .map(|i| {
+                let k = take_row(&mut digits, self.inputs + i, base);
+                let x = take_row(&mut digits, self.inputs + i, base);
+                let (op, oracle) = self.operations[digits.next().unwrap() as usize];
+                let mut y = vec![F::ZERO; base];
+                y[self.inputs + i] = F::ONE;
+                DynConstraint {
+                    op,
+                    oracle,
+                    k,
+                    x,
+                    y,
+                }
+            })
+            .collect();
+        DynAlgebraicRepresentation {
+            base,
+            m,
+            constraints,
+        }
+    }
+}
+
+// The next `len` digits as the first entries of a row of length `base`.
+fn take_row<F: Field>(digits: &mut impl Iterator<Item = u64>, len: usize, base: usize) -> Vec<F> {
+    let mut row: Vec<F> = digits.take(len).map(F::from_u64).collect();
+    row.resize(base, F::ZERO);
+    row
+}
+
+// Appends the first `len` entries of `row` as digits, `None` if any later entry is not
+// zero.
+fn push_row<F: Field>(digits: &mut Vec<u64>, row: &[F], len: usize) -> Option<()> {
+    if row[len..].iter().any(|e| !e.is_zero()) {
+        return None;
+    }
+    digits.extend(row[..len].iter().map(|e| e.to_u64()));
+    Some(())
+}
+
+impl<F: Field> fmt::Display for ProgramSpace<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operations = self
+            .operations
+            .iter()
+            .map(|(op, oracle)| format!("{op:?}{oracle}"))
+            .join(" ");
+        write!(
+            f,
+            "{} inputs, {} queries, {} outputs over a field of order {} with {operations}",
+            self.inputs,
+            self.queries,
+            self.outputs,
+            F::ORDER
+        )?;
+        if self.last_answer_in_output {
+            write!(f, ", using the last answer")?;
+        }
+        Ok(())
+    }
+}
+
+impl<F: Field> IntoIterator for &ProgramSpace<F> {
+    type Item = DynAlgebraicRepresentation<F>;
+    type IntoIter = Programs<F>;
+
+    fn into_iter(self) -> Programs<F> {
+        self.iter()
+    }
+}
+
+/// The iterator returned by `ProgramSpace::iter`.
+#[derive(Debug, Clone)]
+pub struct Programs<F: Field> {
+    space: ProgramSpace<F>,
+    radices: Vec<u64>,
+    digits: Vec<u64>,
+    done: bool,
+}
+
+impl<F: Field> Iterator for Programs<F> {
+    type Item = DynAlgebraicRepresentation<F>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let p = self.space.program(&self.digits);
+        // Advance the odometer, the last digit is the fastest.
+        self.done = true;
+        for (digit, &radix) in self.digits.iter_mut().zip(&self.radices).rev() {
+            *digit += 1;
+            if *digit < radix {
+                self.done = false;
+                break;
+            }
+            *digit = 0;
+        }
+        Some(p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::Gf3;
+    use Operation::*;
+
+    #[test]
+    fn check_number_of_programs() {
+        let space = ProgramSpace::<Gf2>::new(2, 2, 1);
+        // 2^4 output rows, 2^2 * 2^2 rows for the first query and 2^3 * 2^3 for the second.
+        assert_eq!(space.num_programs(), 16 * 16 * 64);
+        assert_eq!(space.iter().count(), 16 * 16 * 64);
+
+        let space = ProgramSpace::<Gf3>::new(1, 3, 2).with_operations(&[(E, 0), (D, 1)]);
+        let expected = 3u128.pow(8 + 2 + 4 + 6) * 2u128.pow(3);
+        assert_eq!(space.num_programs(), expected);
+        let space = ProgramSpace::<Gf2>::new(2, 1, 1).with_operations(&[]);
+        assert_eq!((space.num_programs(), space.iter().count()), (0, 0));
+
+        let space = ProgramSpace::<Gf2>::new(10, 5, 3);
+        assert_eq!(space.checked_num_programs(), None);
+        let mut p = space.unrank(u128::MAX).unwrap();
+        assert_eq!(space.rank(&p), Some(u128::MAX));
+        // The slowest digit is the first output coefficient, beyond any `u128` index.
+        p.m[0][0] = Gf2::ONE;
+        assert_eq!(space.rank(&p), None);
+    }
+
+    #[test]
+    fn check_programs_are_valid() {
+        let space = ProgramSpace::<Gf3>::new(1, 2, 1)
+            .with_operations(&[(E, 0), (H, 0)])
+            .with_last_answer_in_output();
+        assert_eq!(space.num_programs(), 3u128.pow(2 + 2 + 4) * 4);
+        for p in &space {
+            assert_eq!(p.validate(), Ok(()));
+            assert_eq!(p.shape(), space.shape());
+            assert_eq!(p.m[0][2], Gf3::ONE);
+        }
+    }
+
+    #[test]
+    fn check_order() {
+        let space = ProgramSpace::<Gf2>::new(1, 1, 1).with_operations(&[(E, 0), (D, 0)]);
+        let programs: Vec<_> = space.iter().take(4).collect();
+        let ops: Vec<_> = programs.iter().map(|p| p.constraints[0].op).collect();
+        assert_eq!(ops, [E, D, E, D]);
+        assert_eq!(programs[1].constraints[0].x, [0, 0].map(Gf2::from_u64));
+        assert_eq!(programs[2].constraints[0].x, [1, 0].map(Gf2::from_u64));
+        assert!(space.iter_from(5).eq(space.iter().skip(5)));
+        assert_eq!(space.iter_from(space.num_programs()).next(), None);
+        let last = space.iter().last().unwrap();
+        assert_eq!(last.m[0], [1, 1].map(Gf2::from_u64));
+    }
+
+    #[test]
+    fn check_rank_and_unrank() {
+        let space = ProgramSpace::<Gf3>::new(1, 2, 1)
+            .with_operations(&[(E, 0), (D, 1)])
+            .with_last_answer_in_output();
+        for (index, p) in space.iter().enumerate().step_by(97) {
+            assert_eq!(space.unrank(index as u128).as_ref(), Some(&p));
+            assert_eq!(space.rank(&p), Some(index as u128));
+        }
+        let n = space.num_programs();
+        assert_eq!(space.rank(&space.unrank(n - 1).unwrap()), Some(n - 1));
+        assert_eq!(space.unrank(n), None);
+
+        // Programs outside of the space have no index.
+        let mut p = space.unrank(12345).unwrap();
+        p.constraints[1].oracle = 0;
+        assert_eq!(space.rank(&p), None);
+        let mut p = space.unrank(12345).unwrap();
+        p.m[0][2] = Gf3::ZERO;
+        assert_eq!(space.rank(&p), None);
+        let mut p = space.unrank(12345).unwrap();
+        p.constraints[0].k[1] = Gf3::ONE;
+        assert_eq!(space.rank(&p), None);
+        let p = ProgramSpace::<Gf3>::new(1, 2, 2).unrank(0).unwrap();
+        assert_eq!(space.rank(&p), None);
+    }
+}
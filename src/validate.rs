@@ -0,0 +1,261 @@
+//! Checks that programs and collision structures are well formed.
+//!
+//! A Linicrypt program is valid if the `y` row of every query is a unit vector for
+//! a coordinate no earlier query writes to, and the `k` and `x` rows of query `i`
+//! only use the inputs and the outputs of the queries before `i`. The inputs are all
+//! coordinates no query writes to.
+
+use std::fmt;
+
+use crate::field::Field;
+use crate::{CollisionStructureTrait, Program};
+
+/// The ways a program or a collision structure can be ill formed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// A program without any output row.
+    NoOutputs,
+    /// A row does not have one entry for every coordinate of the base vector.
+    RowLength { expected: usize, found: usize },
+    /// The `y` row of the query is not a unit vector.
+    OutputNotUnitVector { query: usize },
+    /// The query writes its output to `coordinate`, like an earlier query.
+    OutputNotFresh { query: usize, coordinate: usize },
+    /// The `k` or `x` row of the query uses `coordinate`, the output of the query
+    /// itself or of a later one.
+    UsesLaterOutput { query: usize, coordinate: usize },
+    /// The permutation of a collision structure is not a permutation of the queries.
+    NotAPermutation { permutation: Vec<usize> },
+    /// A collision structure needs between 1 and the number of queries different queries.
+    DifferentQueries { found: usize, queries: usize },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::NoOutputs => write!(f, "the program has no outputs"),
+            ValidationError::RowLength { expected, found } => {
+                write!(
+                    f,
+                    "expected rows of length {expected}, found one of length {found}"
+                )
+            }
+            ValidationError::OutputNotUnitVector { query } => {
+                write!(f, "the y row of query {query} is not a unit vector")
+            }
+            ValidationError::OutputNotFresh { query, coordinate } => write!(
+                f,
+                "query {query} writes to coordinate {coordinate}, which an earlier query writes to"
+            ),
+            ValidationError::UsesLaterOutput { query, coordinate } => write!(
+                f,
+                "query {query} uses coordinate {coordinate} before it is computed"
+            ),
+            ValidationError::NotAPermutation { permutation } => {
+                write!(f, "{permutation:?} is not a permutation")
+            }
+            ValidationError::DifferentQueries { found, queries } => write!(
+                f,
+                "a collision structure for {queries} queries needs between 1 and {queries} \
+                 different queries, found {found}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Checks that `p` is a valid Linicrypt program.
+pub fn validate_program<F: Field>(p: &impl Program<F>) -> Result<(), ValidationError> {
+    if p.num_outputs() == 0 {
+        return Err(ValidationError::NoOutputs);
+    }
+    let check_length = |row: &[F]| {
+        if row.len() == p.base() {
+            Ok(())
+        } else {
+            Err(ValidationError::RowLength {
+                expected: p.base(),
+                found: row.len(),
+            })
+        }
+    };
+    for j in 0..p.num_outputs() {
+        check_length(&p.output(j))?;
+    }
+
+    let mut known = vec![true; p.base()];
+    for i in 0..p.num_queries() {
+        let c = p.query(i);
+        [c.k, c.x, c.y].into_iter().try_for_each(check_length)?;
+        let coordinate =
+            answer_coordinate(c.y).ok_or(ValidationError::OutputNotUnitVector { query: i })?;
+        if !known[coordinate] {
+            return Err(ValidationError::OutputNotFresh {
+                query: i,
+                coordinate,
+            });
+        }
+        known[coordinate] = false;
+    }
+    // Now only the inputs are known, the outputs become known query by query.
+    for i in 0..p.num_queries() {
+        let c = p.query(i);
+        for row in [c.k, c.x] {
+            let unknown = row
+                .iter()
+                .zip(&known)
+                .position(|(e, &k)| !e.is_zero() && !k);
+            if let Some(coordinate) = unknown {
+                return Err(ValidationError::UsesLaterOutput {
+                    query: i,
+                    coordinate,
+                });
+            }
+        }
+        known[answer_coordinate(c.y).unwrap()] = true;
+    }
+    Ok(())
+}
+
+/// Checks that `cs` has a real permutation and at least one different query.
+pub fn validate_cs(cs: &(impl CollisionStructureTrait + ?Sized)) -> Result<(), ValidationError> {
+    let permutation = cs.permutation();
+    let mut seen = vec![false; permutation.len()];
+    for &i in permutation {
+        match seen.get_mut(i) {
+            Some(seen @ false) => *seen = true,
+            _ => {
+                return Err(ValidationError::NotAPermutation {
+                    permutation: permutation.to_vec(),
+                })
+            }
+        }
+    }
+    let found = cs.types().len();
+    if !(1..=permutation.len()).contains(&found) {
+        return Err(ValidationError::DifferentQueries {
+            found,
+            queries: permutation.len(),
+        });
+    }
+    Ok(())
+}
+
+/// The coordinate the output of a query is written to, if its row is a unit vector.
+pub(crate) fn answer_coordinate<F: Field>(row: &[F]) -> Option<usize> {
+    let j = row.iter().position(|e| !e.is_zero())?;
+    let rest_zero = row[j + 1..].iter().all(|e| e.is_zero());
+    (row[j] == F::ONE && rest_zero).then_some(j)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dynamic::DynAlgebraicRepresentation;
+    use crate::field::Gf2;
+    use crate::linalg::gf2_row;
+    use crate::Operation::*;
+    use crate::{AlgebraicRepresentation, CollisionStructure, Direction};
+
+    fn cascade() -> AlgebraicRepresentation<5, 2, 1> {
+        // y0 = E(a, b), y1 = E(c, y0), output y1
+        AlgebraicRepresentation::new(
+            [0, 0, 0, 0, 1],
+            [
+                (E, [1, 0, 0, 0, 0], [0, 1, 0, 0, 0], [0, 0, 0, 1, 0]),
+                (E, [0, 0, 1, 0, 0], [0, 0, 0, 1, 0], [0, 0, 0, 0, 1]),
+            ],
+        )
+    }
+
+    #[test]
+    fn check_valid_program() {
+        assert_eq!(cascade().validate(), Ok(()));
+    }
+
+    #[test]
+    fn check_invalid_programs() {
+        let mut p = cascade();
+        p.constraints[1].y = gf2_row([0, 0, 0, 1, 1]);
+        assert_eq!(
+            p.validate(),
+            Err(ValidationError::OutputNotUnitVector { query: 1 })
+        );
+
+        let mut p = cascade();
+        p.constraints[1].y = gf2_row([0, 0, 0, 1, 0]);
+        assert_eq!(
+            p.validate(),
+            Err(ValidationError::OutputNotFresh {
+                query: 1,
+                coordinate: 3
+            })
+        );
+
+        let mut p = cascade();
+        p.constraints[0].x = gf2_row([0, 1, 0, 0, 1]);
+        assert_eq!(
+            p.validate(),
+            Err(ValidationError::UsesLaterOutput {
+                query: 0,
+                coordinate: 4
+            })
+        );
+
+        let mut p = DynAlgebraicRepresentation::<Gf2>::from(cascade());
+        p.m[0].pop();
+        assert_eq!(
+            p.validate(),
+            Err(ValidationError::RowLength {
+                expected: 5,
+                found: 4
+            })
+        );
+    }
+
+    #[test]
+    fn check_invalid_collision_structures() {
+        use Direction::*;
+        let cs = CollisionStructure {
+            permutation: [1, 1],
+            cs_type: [F],
+        };
+        assert_eq!(
+            validate_cs(&cs),
+            Err(ValidationError::NotAPermutation {
+                permutation: vec![1, 1]
+            })
+        );
+        let cs = CollisionStructure {
+            permutation: [1, 0],
+            cs_type: [],
+        };
+        assert_eq!(
+            validate_cs(&cs),
+            Err(ValidationError::DifferentQueries {
+                found: 0,
+                queries: 2
+            })
+        );
+        assert!(CollisionStructure::try_new([0, 0], [F, F]).is_err());
+        assert!(CollisionStructure::try_new([1, 0], [B]).is_ok());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "is not a collision structure for 2 queries")]
+    fn check_has_cs_validates() {
+        let cs = CollisionStructure {
+            permutation: [0, 0],
+            cs_type: [Direction::F, Direction::F],
+        };
+        cascade().has_cs(&cs);
+    }
+
+    #[test]
+    #[should_panic(expected = "query 0 uses coordinate 2 before it is computed")]
+    fn check_constructor_validates() {
+        AlgebraicRepresentation::<3, 1, 1>::new([0, 0, 1], [(E, [0, 0, 1], [0, 1, 0], [0, 0, 1])]);
+    }
+}